@@ -0,0 +1,219 @@
+use crate::config::{ConfigResult, MachineConfig};
+use crate::consts::{self, host, store, ToPath};
+use crate::logging::{log_progress, set_progress_status};
+use crate::platform::*;
+use crate::util::{PathExt, UtilExt};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::channel,
+};
+
+/// Directory inside `DIR_DATA` where bundles are (un)packed
+const DIR_BUNDLE: &str = "bundle";
+
+/// Metadata stored as `bundle.json` inside every bundle
+#[derive(Debug, Serialize, Deserialize)]
+struct BundleInfo {
+    name: String,
+    driver_url: String,
+    system: String,
+}
+
+/// A temporary directory inside `DIR_DATA` which is accessible from the host and the store
+struct BundleDir {
+    host: PathBuf,
+    store: String,
+}
+
+impl BundleDir {
+    fn new(name: &str) -> Result<Self> {
+        let nonce: u32 = rand::random();
+        let dir = format!("{name}-{nonce}");
+        Ok(Self {
+            host: host::DIR_DATA
+                .join(DIR_BUNDLE)
+                .join(&dir)
+                .get_or_create()?
+                .clone(),
+            store: store::DIR_DATA.join_str(DIR_BUNDLE).join_str(&dir).0,
+        })
+    }
+
+    fn remove(self) {
+        self.host.with_extension("tar").remove();
+        self.host.remove();
+    }
+}
+
+pub fn create(name: &str, target_file: &Path) -> Result<()> {
+    let machine = Machine::by_name(name, true)?;
+    if machine.config_status == ConfigStatus::NotInstalled {
+        bail!("Machine '{name}' wasn't installed yet. Install with `codchi rebuild {name}`.");
+    }
+    let store = Driver::store();
+    let machine_dir = store::DIR_CONFIG.join_machine(name);
+
+    set_progress_status(format!("Collecting flake inputs of {name}..."));
+    let archive: Value = store
+        .cmd()
+        .run(
+            "nix",
+            &["flake", "archive", "--json", "--no-write-lock-file"],
+        )
+        .with_cwd(machine_dir.clone())
+        .output_json()?;
+    let mut inputs = Vec::new();
+    collect_paths(&archive, &mut inputs);
+    let system = store.cmd().realpath(&machine_dir.join_str("system"))?;
+
+    let dir = BundleDir::new(name)?;
+    let result = (|| {
+        // secrets must never leave the host
+        let mut cfg = machine.config.clone();
+        cfg.secrets.clear();
+        fs::write(
+            dir.host.join("config.json"),
+            serde_json::to_string_pretty(&cfg)?,
+        )?;
+        fs::write(
+            dir.host.join("bundle.json"),
+            serde_json::to_string_pretty(&BundleInfo {
+                name: name.to_string(),
+                driver_url: cfg
+                    .driver_url
+                    .unwrap_or_else(|| consts::CODCHI_FLAKE_URL.to_string()),
+                system: system.0.clone(),
+            })?,
+        )?;
+
+        set_progress_status(format!("Exporting closure of {name}..."));
+        store
+            .cmd()
+            .script(format!(
+                r#"
+cp flake.nix flake.lock "{dir}/"
+nix-store --export $(nix-store -qR "{system}") {inputs} > "{dir}/closure.nar"
+tar -C "{dir}" -cf "{dir}.tar" .
+"#,
+                dir = dir.store,
+                inputs = inputs.join(" "),
+            ))
+            .with_cwd(machine_dir.clone())
+            .output_ok_streaming(channel().1, |line| {
+                log_progress("bundle", log::Level::Debug, &line)
+            })?;
+
+        set_progress_status(format!("Writing bundle to {target_file:?}..."));
+        let tar = dir.host.with_extension("tar");
+        if fs::rename(&tar, target_file).is_err() {
+            fs::copy(&tar, target_file)
+                .with_context(|| format!("Failed writing bundle to {target_file:?}."))?;
+        }
+        anyhow::Ok(())
+    })();
+    dir.remove();
+    result?;
+
+    log::info!("Success! Bundled machine {name} to {target_file:?}.");
+    Ok(())
+}
+
+pub fn install(bundle_file: &Path) -> Result<Machine> {
+    bundle_file
+        .assert_exists()
+        .with_context(|| format!("Bundle {bundle_file:?} doesn't exist."))?;
+    let store = Driver::store();
+
+    let dir = BundleDir::new("install")?;
+    let result = (|| {
+        set_progress_status(format!("Unpacking {bundle_file:?}..."));
+        fs::copy(bundle_file, dir.host.with_extension("tar"))?;
+        store
+            .cmd()
+            .script(format!(
+                r#"tar -C "{dir}" -xf "{dir}.tar""#,
+                dir = dir.store
+            ))
+            .wait_ok()
+            .context("Failed unpacking bundle. Is it a valid bundle file?")?;
+
+        let info: BundleInfo = serde_json::from_str(
+            &fs::read_to_string(dir.host.join("bundle.json"))
+                .context("Missing 'bundle.json'. Is it a valid bundle file?")?,
+        )?;
+        log::debug!("Installing bundle: {info:?}");
+        let name = &info.name;
+        match MachineConfig::find(name)? {
+            ConfigResult::Exists => bail!("Code machine '{name}' already exists."),
+            ConfigResult::SimilarExists(other) => {
+                bail!("A machine with a similar name ({other}) already exists.")
+            }
+            ConfigResult::None => {}
+        }
+        if info.driver_url != consts::CODCHI_FLAKE_URL {
+            bail!(
+                "The bundle was created with a different version of codchi ({}). Please install \
+the same version of codchi ({}) on this host.",
+                info.driver_url,
+                consts::CODCHI_FLAKE_URL
+            );
+        }
+
+        set_progress_status(format!("Importing closure of {name}..."));
+        store
+            .cmd()
+            .script(format!(
+                r#"nix-store --import < "{dir}/closure.nar" > /dev/null"#,
+                dir = dir.store
+            ))
+            .output_ok_streaming(channel().1, |line| {
+                log_progress("bundle", log::Level::Debug, &line)
+            })?;
+
+        let (lock, _) = MachineConfig::open(name, true)?;
+        let mut cfg: MachineConfig =
+            serde_json::from_str(&fs::read_to_string(dir.host.join("config.json"))?)?;
+        cfg.name = name.clone();
+        cfg.driver_url = None;
+        cfg.write(lock)?;
+        fs::copy(
+            dir.host.join("flake.lock"),
+            host::DIR_CONFIG.join_machine(name).join("flake.lock"),
+        )?;
+        Machine::by_name(name, false)
+    })();
+    dir.remove();
+    let machine = result?;
+
+    (|| {
+        // Asks for all secrets, which `create` removed from the config
+        machine.build(true)?;
+        machine.run_init_script()
+    })()
+    .inspect_err(|_| {
+        if !log::log_enabled!(log::Level::Debug) {
+            let name = &machine.config.name;
+            log::error!("Failed installing machine '{name}'. Removing leftovers...");
+            if let Ok(machine) = Machine::by_name(name, false) {
+                machine.delete(true).ignore();
+            }
+        }
+    })?;
+    Ok(machine)
+}
+
+/// Collect all store paths from the output of `nix flake archive --json`
+fn collect_paths(value: &Value, paths: &mut Vec<String>) {
+    if let Some(path) = value.get("path").and_then(Value::as_str) {
+        paths.push(path.to_string());
+    }
+    if let Some(inputs) = value.get("inputs").and_then(Value::as_object) {
+        for input in inputs.values() {
+            collect_paths(input, paths);
+        }
+    }
+}
@@ -4,9 +4,10 @@ use clap::builder::*;
 use clap::*;
 use clap_verbosity_flag::{InfoLevel, LogLevel, Verbosity};
 use git_url_parse::{GitUrl, Scheme};
-use lazy_regex::regex_is_match;
+use lazy_regex::{regex_captures, regex_is_match};
 use log::Level;
 use std::{
+    fmt::{self, Display},
     path::PathBuf,
     str::FromStr,
     sync::{LazyLock, OnceLock},
@@ -24,6 +25,10 @@ pub static DEBUG: LazyLock<bool> = LazyLock::new(|| {
         >= Level::Debug
 });
 
+#[allow(dead_code)]
+pub static OFFLINE: LazyLock<bool> =
+    LazyLock::new(|| CLI_ARGS.get().is_some_and(|cli| cli.offline));
+
 type DefaultLogLevel = InfoLevel;
 
 #[derive(Debug, Parser, Clone)]
@@ -79,6 +84,12 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub json: bool,
 
+    /// Only use locally cached flake inputs and store paths. Module updates are skipped and
+    /// commands fail fast if something isn't cached. Can be enabled permanently with
+    /// `offline = true` in codchi's config.
+    #[arg(long, global = true)]
+    pub offline: bool,
+
     #[command(subcommand)]
     pub command: Option<Cmd>,
 }
@@ -205,7 +216,8 @@ cargo build
         #[arg(long, short = 'r')]
         keep_remote: bool,
 
-        /// HTTP(S) URL to the git repository.
+        /// HTTP(S) or SSH URL to the git repository. SSH URLs are cloned with the host's SSH
+        /// agent (see `codchi passthrough --ssh-agent`).
         url: CodchiUrl,
 
         #[command(flatten)]
@@ -309,6 +321,41 @@ codchi rebuild <MACHINE_NAME> --no-update
         name: String,
     },
 
+    #[clap(
+        about = "Upgrade the codchi driver of code machines to the version of this codchi \
+installation.",
+        long_about = r#"
+Every code machine depends on the codchi driver (the NixOS module `codchi_driver`) of the codchi
+version it was built with. After codchi itself was updated, `codchi status` marks the driver of
+machines which still use an older one as "Outdated" (or "Unknown" if the machine was built before
+codchi recorded its driver). This command points them to the new driver and rebuilds them without
+fetching updates for their other modules.
+
+By default all installed machines with an outdated or unknown driver are upgraded. A failing machine doesn't
+stop the upgrade of the remaining ones. A report of all upgraded machines is printed at the end.
+"#,
+        after_long_help = r#"
+# EXAMPLES
+
+Upgrade all machines with an outdated driver:
+```
+codchi upgrade
+```
+Upgrade only <MACHINE_1> and <MACHINE_2>:
+```
+codchi upgrade <MACHINE_1> <MACHINE_2>
+```
+"#
+    )]
+    Upgrade {
+        /// Upgrade all installed machines, even if their driver is already up to date.
+        #[arg(long, short = 'a', conflicts_with = "machines")]
+        all: bool,
+
+        /// Machines to be upgraded.
+        machines: Vec<String>,
+    },
+
     #[clap(
         aliases = &["run"],
         about = "Execute a command inside a code machine. \
@@ -337,9 +384,37 @@ If some options conflict with Codchi's options, you can escape them with `--`:
 ```
 codchi exec <MACHINE_NAME> -- PROGRAM [ARGS...]
 ```
+Arguments are passed to `PROGRAM` as-is, so quotes, spaces or globs are not interpreted a second
+time inside the code machine:
+```
+codchi exec <MACHINE_NAME> grep "a b" file.txt
+```
+Use `--shell` to interpret the command with bash, e.g. for pipes or globs inside the code machine:
+```
+codchi exec --shell <MACHINE_NAME> 'ls *.nix | wc -l'
+```
+Run `make` as root in `/home/codchi/project` with an additional environment variable:
+```
+codchi exec --root --cwd project -e DEBUG=1 <MACHINE_NAME> make
+```
+Commands started from a directory inside the home of <MACHINE_NAME> on the host (e.g.
+`~/.local/share/codchi/machine/<MACHINE_NAME>/project` on Linux) run in the corresponding directory
+inside <MACHINE_NAME> (`/home/codchi/project`). With `--map-paths`, host paths in the arguments are
+translated, too:
+```
+codchi exec --map-paths <MACHINE_NAME> cat ~/.local/share/codchi/machine/<MACHINE_NAME>/README.md
+```
+Pipe data through a program inside <MACHINE_NAME>. The exit code of `PROGRAM` is returned by
+`codchi exec`:
+```
+cat data.json | codchi exec --no-tty <MACHINE_NAME> jq .name > name.txt
+```
 "#
     )]
     Exec {
+        #[command(flatten)]
+        options: ExecOptions,
+
         /// Name of the code machine
         name: String,
 
@@ -348,6 +423,83 @@ codchi exec <MACHINE_NAME> -- PROGRAM [ARGS...]
         cmd: Vec<String>,
     },
 
+    #[clap(
+        about = "Copy files and directories between the host and a code machine.",
+        long_about = r#"
+Copy files and directories between the host and a code machine. Paths inside a code machine are
+written as `<MACHINE_NAME>:<PATH>`, where relative paths are relative to `/home/codchi`. Directories
+are copied recursively. Like `cp`, SRC is copied into DEST if DEST is an existing directory.
+Otherwise it is copied to DEST.
+
+Files copied into a code machine are owned by the default user (`codchi`), or by root with
+`--root`. Copying works whether the code machine is running or not. A stopped machine is started
+for the copy and stopped again afterwards.
+"#,
+        after_long_help = r#"
+# EXAMPLES
+
+Copy a file from the host into the home directory of <MACHINE_NAME>:
+```
+codchi cp ./data.csv <MACHINE_NAME>:
+```
+Copy a directory from <MACHINE_NAME> to the host:
+```
+codchi cp <MACHINE_NAME>:project/build ./build
+```
+Copy a config file to a system directory:
+```
+codchi cp --root ./my.conf <MACHINE_NAME>:/etc/my.conf
+```
+"#
+    )]
+    Cp {
+        /// Own the copied files by root inside the code machine (and read as root)
+        #[arg(long)]
+        root: bool,
+
+        /// Source path on the host or `<MACHINE_NAME>:<PATH>`
+        src: CopyLocation,
+
+        /// Destination path on the host or `<MACHINE_NAME>:<PATH>`
+        dst: CopyLocation,
+    },
+
+    #[clap(
+        about = "Print an SSH config for all code machines, e.g. for IDEs.",
+        long_about = r#"
+Print an SSH config with a `Host codchi-<MACHINE_NAME>` block for every code machine. This allows
+IDEs like VS Code (Remote-SSH) or JetBrains Gateway to connect to code machines. No network port is
+opened. Instead, SSH connects through `codchi ssh-proxy`. Codchi generates and manages the keys of
+each code machine in its config directory.
+"#,
+        after_long_help = r#"
+# EXAMPLES
+
+Write the config to a separate file and include it in your SSH config:
+```
+codchi ssh-config > ~/.ssh/codchi_config
+echo "Include ~/.ssh/codchi_config" >> ~/.ssh/config
+```
+Afterwards, connect with:
+```
+ssh codchi-<MACHINE_NAME>
+```
+"#
+    )]
+    SshConfig {},
+
+    #[clap(
+        about = "Connect stdin / stdout to SSH inside a code machine. Used as SSH `ProxyCommand`.",
+        long_about = r#"
+Start a code machine and connect stdin / stdout to an SSH server running inside it. This is meant to
+be used as SSH `ProxyCommand` (see `codchi ssh-config`). No network port is opened.
+"#
+    )]
+    SshProxy {
+        /// Name of the code machine
+        name: String,
+    },
+
     #[clap(
         about = "Delete a code machine with all associated files.",
         long_about = r#"
@@ -363,6 +515,109 @@ codchi exec <MACHINE_NAME> -- PROGRAM [ARGS...]
         name: String,
     },
 
+    #[clap(
+        about = "Configure which host devices are passed through to a code machine.",
+        long_about = r#"
+Configure which host devices are passed through to a code machine. Changes are applied immediately,
+there is no need to reinstall or rebuild the machine. Without options, the current settings are
+printed.
+
+### GPU
+By default (`auto`) all GPUs are passed through if `/dev/dri` contains DRM devices. On hosts
+without GPU, like headless servers or CI runners, no GPU is passed through. On hosts with multiple
+GPUs, select one by its PCI address (`pci=0000:01:00.0`) or vendor id (`vendor=10de`). Use `off`
+to never pass through a GPU.
+
+On Windows, GPU access is managed by WSL and this setting is ignored.
+
+### Wayland, PipeWire and PulseAudio
+X11 is always available inside a code machine. Additionally, the sockets of the host's Wayland
+compositor, PipeWire and PulseAudio server can be passed through. They are off by default. If
+enabled, `WAYLAND_DISPLAY`, `XDG_RUNTIME_DIR`, `PIPEWIRE_REMOTE` and `PULSE_SERVER` are set in all
+`codchi exec` sessions, including desktop shortcuts.
+
+On Windows, WSLg provides Wayland and PulseAudio and these settings are ignored.
+
+### SSH Agent and Git Identity
+The host's SSH agent (`$SSH_AUTH_SOCK`) can be passed through, so private repositories can be
+cloned and pushed without creating keys inside the machine. `SSH_AUTH_SOCK` is set in all shells
+inside the machine. The socket of the agent is updated every time the machine is started.
+
+With `--git-identity`, `user.name` and `user.email` of the host's global git config are written to
+`~/.gitconfig` inside the machine on every start.
+
+On Windows, SSH agent forwarding isn't supported yet.
+
+### GPG Agent
+The extra socket of the host's gpg-agent can be passed through to sign commits (`git commit -S`)
+inside the machine without copying private keys into it. The extra socket only allows signing and
+decryption with the keys of the host. On every start, the public keyring of the host is imported
+into the machine. The machine must be rebuilt once after enabling this setting.
+
+On Windows, GPG agent forwarding isn't supported yet.
+"#,
+        after_long_help = r#"
+# EXAMPLES
+
+Show the current settings of <MACHINE_NAME>:
+```
+codchi passthrough <MACHINE_NAME>
+```
+Only pass through the NVIDIA GPU:
+```
+codchi passthrough <MACHINE_NAME> --gpu vendor=10de
+```
+Disable GPU passthrough:
+```
+codchi passthrough <MACHINE_NAME> --gpu off
+```
+Run graphical applications natively on Wayland and enable audio:
+```
+codchi passthrough <MACHINE_NAME> --wayland true --pipewire true --pulseaudio true
+```
+Use the SSH agent and git identity of the host:
+```
+codchi passthrough <MACHINE_NAME> --ssh-agent true --git-identity true
+```
+Sign commits with the GPG keys of the host:
+```
+codchi passthrough <MACHINE_NAME> --gpg-agent true --git-identity true
+```
+"#
+    )]
+    Passthrough {
+        /// Name of the code machine
+        name: String,
+
+        /// GPU passthrough: `auto`, `off`, `pci=<PCI_ADDRESS>` or `vendor=<VENDOR_ID>`
+        #[arg(long)]
+        gpu: Option<GpuPassthrough>,
+
+        /// Pass through the Wayland socket of the host
+        #[arg(long)]
+        wayland: Option<bool>,
+
+        /// Pass through the PipeWire socket of the host
+        #[arg(long)]
+        pipewire: Option<bool>,
+
+        /// Pass through the PulseAudio socket of the host
+        #[arg(long)]
+        pulseaudio: Option<bool>,
+
+        /// Pass through the SSH agent of the host
+        #[arg(long)]
+        ssh_agent: Option<bool>,
+
+        /// Pass through the gpg-agent of the host and import its public keys on start
+        #[arg(long)]
+        gpg_agent: Option<bool>,
+
+        /// Copy the git user name and email of the host into the machine on start
+        #[arg(long)]
+        git_identity: Option<bool>,
+    },
+
     #[clap(
         group(ArgGroup::new("exclusive").args(&["all", "machines"])),
         about = "Perform garbage collection of old nix store paths.",
@@ -377,17 +632,24 @@ never get freed. To also delete old generations, use `--delete-old` with an opti
 (in days). Note that only explicitly listed machines (`MACHINES...`) will be processed. To
 process all installed machines, use `--all`.
 
-### WARNING
+### Local gcroots
 In Codchi, the Nix Store is shared across all code machines, which means that the garbage collector
 also shares a directory of roots ("gcroots") that should be preserved, such as the current system
-configuration of each code machine. On a vanilla NixOS system, local gcroots (such as those created
-by `nix build` or direnv) are automatically registered and protected from garbage collection.
-However, this doesn't work in Codchi at the moment, because the garbage collector has a different
-view on the file system of each code machine. As a result, the local gcroots of individual machines
-become invalid. For example, a gcroot pointing to `/home/codchi/result` on machine `foo` is invalid
-from the perspective of Codchi, as the correct path would be `/data/machine/foo/result`.
-
-This isn't a huge issue, as the deleted store paths can be redownloaded or rebuilt.
+configuration of each code machine. Local gcroots (such as those created by `nix build` or direnv)
+point to the file system of the individual machine, which the garbage collector can't see. For
+example, a gcroot pointing to `/home/codchi/result` on machine `foo` is invalid from the
+perspective of the store, as the correct path would be `/data/machine/foo/result`. Therefore
+Codchi translates all local gcroots inside `/home/codchi` into the store's view before collecting
+garbage, so `nix build` results and direnv shells survive. Gcroots outside of `/home/codchi` (e.g.
+in `/tmp`) are still deleted, but the store paths can be redownloaded or rebuilt.
+
+### Retention Policy
+Instead of running `codchi gc` manually, a retention policy can be configured in the `[gc]` section
+of codchi's config (`keep_generations`, `delete_older_than`, `max_store_size` and
+`min_free_space`, both in GiB). It is applied to all machines after each successful build of a
+machine (e.g. by `codchi rebuild`, `codchi init` or `codchi module add`), but only if the store
+exceeds `max_store_size` or free disk space falls below `min_free_space` (if one of them is set). `--policy` applies it immediately regardless of these thresholds. Each run is logged
+to `log/gc.log` in codchi's data directory.
 "#,
         after_long_help = r#"
 ### Large WSL Distributions
@@ -420,6 +682,14 @@ Delete all but the current generation from each installed machine:
 ```
 codchi gc --all --delete-old
 ```
+Show what would be deleted without deleting anything:
+```
+codchi gc --all --delete-old 30 --dry-run
+```
+Apply the retention policy from codchi's config now:
+```
+codchi gc --policy
+```
 "#
     )]
     GC {
@@ -431,6 +701,15 @@ codchi gc --all --delete-old
         #[arg(long, short = 'a', requires = "delete_old")]
         all: bool,
 
+        /// Apply the retention policy from codchi's config (`[gc]`) to all machines
+        #[arg(long, conflicts_with_all = ["delete_old", "all", "machines"])]
+        policy: bool,
+
+        /// Only show which generations would be deleted and how much space would be freed. No
+        /// generations or store paths are deleted.
+        #[arg(long)]
+        dry_run: bool,
+
         /// Machines to be processed. Requires `--delete-old`.
         #[arg(requires = "delete_old")]
         machines: Vec<String>,
@@ -451,6 +730,97 @@ See the following docs on how to register the completions with your shell:
         #[arg(value_enum)]
         shell: clap_complete_command::Shell,
     },
+    #[clap(
+        about = "Check the setup of codchi and print hints how to fix problems.",
+        long_about = r#"
+Run a series of checks and print pass / warn / fail with remediation for each. Use `--json` to
+attach the result to a support ticket. Exits with a non-zero status if any check fails.
+
+Checked are (depending on the platform):
+- LXD / WSL installation and permissions
+- ID mappings in `/etc/subuid` and `/etc/subgid`
+- GPU and X11 availability
+- Store container and nix daemon
+- Free disk space
+- Configs of all code machines
+- TLS connection to all binary caches
+"#
+    )]
+    Doctor {},
+
+    #[clap(
+        about = "Show the logs of builds, code machines and the store.",
+        long_about = r#"
+The log of every build (by `codchi init`, `codchi rebuild`, ...) and every garbage collection is
+saved with the raw JSON output of nix. By default, the latest build log of a machine is shown as
+readable messages. Use `--raw` for the original lines, e.g. to attach them to an issue.
+
+Saved logs are kept until they are pruned with `--prune`.
+"#,
+        after_long_help = r#"
+# EXAMPLES
+
+Show the log of the latest build of <MACHINE_NAME>:
+```
+codchi logs <MACHINE_NAME>
+```
+Show the log of the build before:
+```
+codchi logs <MACHINE_NAME> --build 2
+```
+Follow the log of the machine's initialization and services:
+```
+codchi logs <MACHINE_NAME> --init -f
+```
+Show the log of the store and of the latest garbage collection:
+```
+codchi logs --store
+codchi logs --store --build 1
+```
+Delete all but the latest 5 build logs of each machine:
+```
+codchi logs --prune 5
+```
+"#
+    )]
+    Logs {
+        /// Name of the code machine
+        #[arg(required_unless_present_any = ["store", "prune"])]
+        name: Option<String>,
+
+        /// Show the N-th latest build (1 is the latest). With `--store`, show the N-th latest
+        /// garbage collection.
+        #[arg(long, short = 'b', value_name = "N", conflicts_with = "init")]
+        build: Option<usize>,
+
+        /// Show the log of the machine's initialization and services
+        #[arg(long, short = 'i')]
+        init: bool,
+
+        /// Show the log of the store instead of a machine
+        #[arg(long, short = 's', conflicts_with_all = ["name", "init"])]
+        store: bool,
+
+        /// Wait for new lines until interrupted
+        #[arg(long, short = 'f')]
+        follow: bool,
+
+        /// Print the raw JSON output of nix instead of readable messages
+        #[arg(long)]
+        raw: bool,
+
+        /// Delete all but the latest KEEP build logs of each machine and the logs of deleted
+        /// machines
+        #[arg(
+            long,
+            value_name = "KEEP",
+            num_args = 0..=1,
+            default_missing_value = "10",
+            conflicts_with_all = ["name", "build", "init", "store", "follow", "raw"]
+        )]
+        prune: Option<usize>,
+    },
+
     ///
     /// Start the codchi tray if not running.
     #[clap(hide = true)]
@@ -468,12 +838,38 @@ See the following docs on how to register the completions with your shell:
     #[command(subcommand)]
     #[clap(about = "Utilities for interacting with the `codchistore` container.")]
     Store(StoreCmd),
+
+    #[command(subcommand)]
+    #[clap(
+        about = "Distribute code machines to hosts without internet access.",
+        long_about = r#"
+A bundle contains the configuration of a code machine, its complete system closure and the sources
+of all flake inputs. Installing a bundle doesn't require network access. Note that only the system
+is bundled, files inside the machine (like `/home/codchi`) are not. Use `codchi tar` to export them.
+
+Both hosts need to run the same version of Codchi. Secrets are not included in the bundle. Like
+`codchi init`, `codchi bundle install` asks for each secret of the machine while building it, so
+installing a machine with secrets requires an interactive terminal.
+"#,
+        after_long_help = r#"
+# EXAMPLES
+
+Create a bundle of <MACHINE_NAME> on a host with internet access:
+```
+codchi bundle create <MACHINE_NAME> machine.bundle
+```
+Install it on another host without internet access:
+```
+codchi bundle install machine.bundle
+```
+"#
+    )]
+    Bundle(BundleCmd),
 }
 
 mod module {
     use super::*;
     use core::fmt;
-    use lazy_regex::regex_captures;
     use std::fmt::Display;
 
     pub static CODCHI_DRIVER_MODULE: &str = "codchi_driver";
@@ -697,6 +1093,149 @@ codchi module set <MACHINE_NAME> <MODULE_NAME> --url=my-project-name
     }
 }
 
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct ExecOptions {
+    /// Interpret the command with bash instead of passing the arguments as-is
+    #[arg(long)]
+    pub shell: bool,
+
+    /// Working directory inside the code machine. Relative paths are relative to `/home/codchi`.
+    ///
+    /// By default, the current directory is used if it lies inside the home of the code machine
+    /// on the host. Otherwise, the command starts in `/home/codchi`.
+    #[arg(long, value_name = "PATH")]
+    pub cwd: Option<String>,
+
+    /// Set an environment variable. Can be given multiple times.
+    #[arg(long = "env", short = 'e', value_name = "KEY=VAL")]
+    pub env: Vec<EnvVar>,
+
+    /// Run the command as root instead of the default user
+    #[arg(long)]
+    pub root: bool,
+
+    /// Don't allocate a terminal. Use this for piping data through stdin / stdout in scripts.
+    #[arg(long)]
+    pub no_tty: bool,
+
+    /// Translate arguments which are host paths inside the home of the code machine into paths
+    /// inside the code machine. Relative paths are only translated if they exist.
+    #[arg(long)]
+    pub map_paths: bool,
+}
+
+/// A path on the host or inside a code machine (`<MACHINE_NAME>:<PATH>`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CopyLocation {
+    Host(PathBuf),
+    Machine { name: String, path: String },
+}
+
+impl FromStr for CopyLocation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Windows paths like `C:\foo` are no machine paths
+        if cfg!(windows) && regex_is_match!(r"^[a-zA-Z]:[\\/]", s) {
+            return Ok(CopyLocation::Host(PathBuf::from(s)));
+        }
+        match regex_captures!(r"^([a-zA-Z0-9_-]+):(.*)$", s) {
+            Some((_, name, path)) => Ok(CopyLocation::Machine {
+                name: name.to_string(),
+                path: path.to_string(),
+            }),
+            None => Ok(CopyLocation::Host(PathBuf::from(s))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvVar {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for EnvVar {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if regex_is_match!(r"^[a-zA-Z_][a-zA-Z0-9_]*$", key) => Ok(EnvVar {
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(format!(
+                "Invalid environment variable '{s}'. Expected 'KEY=VAL' where KEY consists of \
+letters, digits and underscores."
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum GpuPassthrough {
+    /// Pass through all GPUs if there are any
+    #[default]
+    Auto,
+    Off,
+    /// Pass through the GPU with this PCI address
+    Pci(String),
+    /// Pass through all GPUs of this vendor
+    Vendor(String),
+}
+
+impl Display for GpuPassthrough {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GpuPassthrough::Auto => f.write_str("auto"),
+            GpuPassthrough::Off => f.write_str("off"),
+            GpuPassthrough::Pci(address) => write!(f, "pci={address}"),
+            GpuPassthrough::Vendor(id) => write!(f, "vendor={id}"),
+        }
+    }
+}
+
+impl FromStr for GpuPassthrough {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            None if s == "auto" => Ok(GpuPassthrough::Auto),
+            None if s == "off" => Ok(GpuPassthrough::Off),
+            Some(("pci", address))
+                if regex_is_match!(
+                    r"^([0-9a-fA-F]{4}:)?[0-9a-fA-F]{2}:[0-9a-fA-F]{2}\.[0-7]$",
+                    address
+                ) =>
+            {
+                // the PCI domain is optional, but required by LXD
+                if address.len() == 7 {
+                    Ok(GpuPassthrough::Pci(format!(
+                        "0000:{}",
+                        address.to_lowercase()
+                    )))
+                } else {
+                    Ok(GpuPassthrough::Pci(address.to_lowercase()))
+                }
+            }
+            Some(("vendor", id)) => {
+                let id = id.trim_start_matches("0x");
+                if regex_is_match!(r"^[0-9a-fA-F]{4}$", id) {
+                    Ok(GpuPassthrough::Vendor(id.to_lowercase()))
+                } else {
+                    Err(format!(
+                        "Invalid vendor id '{id}'. Expected 4 hex digits like '10de'."
+                    ))
+                }
+            }
+            _ => Err(format!(
+                "Invalid GPU setting '{s}'. Expected 'auto', 'off', 'pci=<PCI_ADDRESS>' or \
+'vendor=<VENDOR_ID>'."
+            )),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, ValueEnum)]
 pub enum NixpkgsLocation {
     Local,
@@ -758,7 +1297,127 @@ pub enum StoreCmd {
 / requiring any services. Usefull for debugging.")]
     Debug,
 
+    #[clap(
+        about = "Show the binary caches and credentials nix uses inside `codchistore`.",
+        long_about = r#"
+Show the binary caches and credentials nix uses inside `codchistore`. Additional caches, their
+public keys, a netrc file and access tokens can be configured in the `[store]` section of codchi's
+config. Access tokens are not printed, only the hosts they are configured for.
+"#
+    )]
+    Config,
+
+    #[clap(
+        about = "Show how much store space each machine uses.",
+        long_about = r#"
+Show how much store space each machine uses. Store paths which are only used by a single machine
+are counted for this machine. Paths which are used by multiple machines or by the store itself are
+counted as shared. Paths which aren't used at all are garbage, which is deleted by `codchi gc`.
+Sizes are the uncompressed sizes of the store paths, so the actual disk usage might be lower.
+"#
+    )]
+    Du,
+
+    #[clap(
+        about = "Check the store for corrupted paths.",
+        long_about = r#"
+Check the contents of all store paths against their hashes with `nix store verify`. Interrupted
+builds, a full disk or a crash can leave the shared store inconsistent. With `--machine`, only the
+closure of the current system of this machine is checked. With `--repair`, corrupted paths are
+fetched again from the binary caches or rebuilt. Exits with a non-zero status if corrupted paths
+remain or nix reports errors.
+"#,
+        after_long_help = r#"
+# EXAMPLES
+
+Check and repair the closure of <MACHINE_NAME>:
+```
+codchi store verify --repair --machine <MACHINE_NAME>
+```
+"#
+    )]
+    Verify {
+        /// Repair corrupted paths
+        #[arg(long)]
+        repair: bool,
+
+        /// Only check the closure of this machine
+        #[arg(long, short)]
+        machine: Option<String>,
+    },
+
     #[cfg(target_os = "windows")]
     #[clap(about = "Try to restore the `codchistore` container without deleting /nix/store.")]
     Recover,
+
+    #[cfg(target_os = "linux")]
+    #[clap(
+        about = "Move codchi's data directory (store, machine files and logs) to another location.",
+        long_about = r#"
+Move codchi's data directory (store, machine files and logs) to another location, e.g. another
+disk. All code machines and the store are stopped first. If TARGET is on the same file system, the
+data is just renamed. Otherwise it is copied and the old data is deleted once the store runs with
+the new location. Afterwards `data_dir` in codchi's config is updated and all LXD containers use
+the new location.
+"#,
+        after_long_help = r#"
+# EXAMPLES
+
+Move all data to a bigger disk:
+```
+codchi store move /mnt/big-disk/codchi
+```
+"#
+    )]
+    Move {
+        /// New data directory. Must be empty or not exist.
+        target: PathBuf,
+    },
+
+    #[cfg(target_os = "linux")]
+    #[clap(
+        about = "Recreate the `codchistore` container without deleting /nix/store or any data.",
+        long_about = r#"
+Recreate the `codchistore` LXD container from the image shipped with codchi and re-attach all
+devices. The Nix store, its database, codchi's config and all machine files are kept. Use this if
+the store container is broken, e.g. after manual changes with `lxc`. All code machines are stopped
+first.
+"#
+    )]
+    Repair,
+
+    #[cfg(target_os = "linux")]
+    #[clap(
+        about = "Rebuild the store from scratch and repair all store paths.",
+        long_about = r#"
+Rebuild the store from scratch if `codchi store repair` isn't enough. All code machines are
+stopped, the `codchistore` container and the state of the store (profiles, the store's own config
+and all gcroots except the ones created inside code machines, e.g. by `nix build` or direnv) are
+deleted and a fresh store is initialized. Store paths and machine files are kept. Gcroots which
+were created manually inside the store are lost.
+Afterwards every existing generation of each code machine is registered as a gcroot again and all
+store paths are verified with `nix store verify --all --repair`. Paths which are corrupted are
+fetched again from the binary caches.
+"#
+    )]
+    Reset,
+}
+
+#[derive(Debug, Subcommand, Clone)]
+pub enum BundleCmd {
+    /// Export a code machine with its complete closure to a bundle file.
+    Create {
+        /// Name of the code machine
+        name: String,
+
+        /// Path of the bundle file.
+        target_file: PathBuf,
+    },
+
+    /// Install a code machine from a bundle file without network access. Asks for the machine's
+    /// secrets.
+    Install {
+        /// Path of the bundle file.
+        bundle_file: PathBuf,
+    },
 }
@@ -16,6 +16,126 @@ pub struct CodchiConfig {
 
     // $XDG_DATA_HOME/codchi by default
     pub data_dir: Option<String>,
+
+    /// Always run in offline mode (same as `--offline`)
+    #[serde(default)]
+    pub offline: bool,
+
+    #[serde(default)]
+    pub store: StoreConfig,
+
+    #[serde(default)]
+    pub network: NetworkConfig,
+
+    #[serde(default)]
+    pub gc: GcConfig,
+}
+
+/// Retention policy which is applied after each successful build of a machine (e.g. by `codchi
+/// rebuild`, `codchi init` or `codchi module add`) and by `codchi gc --policy`
+#[derive(Clone, Debug, Deserialize, Serialize, Default, PartialEq, Eq)]
+pub struct GcConfig {
+    /// Always keep the newest N generations of each machine
+    pub keep_generations: Option<u32>,
+
+    /// Delete generations which are older than this many days (like `codchi gc --delete-old`)
+    pub delete_older_than: Option<u16>,
+
+    /// Only collect garbage automatically if the store exceeds this size (in GiB)
+    pub max_store_size: Option<u64>,
+
+    /// Only collect garbage automatically if less than this much disk space is free (in GiB)
+    pub min_free_space: Option<u64>,
+}
+
+impl GcConfig {
+    /// Whether any generations can be deleted by this policy
+    pub fn is_enabled(&self) -> bool {
+        self.keep_generations.is_some() || self.delete_older_than.is_some()
+    }
+}
+
+/// Proxy and TLS settings which are propagated to the store and all machines
+#[derive(Clone, Debug, Deserialize, Serialize, Default, PartialEq, Eq)]
+pub struct NetworkConfig {
+    pub http_proxy: Option<String>,
+    pub https_proxy: Option<String>,
+    pub no_proxy: Option<String>,
+
+    /// Additional CA certificates (PEM files on the host), e.g. of a TLS-intercepting proxy
+    #[serde(default)]
+    pub ca_certificates: Vec<String>,
+}
+
+impl NetworkConfig {
+    /// Environment variables for the proxies. Both lower and upper case variants are set, because
+    /// tools disagree which one to use. Unset proxies are empty, so that a previously configured
+    /// proxy is overridden.
+    pub fn proxy_env(&self) -> Vec<(String, String)> {
+        [
+            ("http_proxy", &self.http_proxy),
+            ("https_proxy", &self.https_proxy),
+            ("no_proxy", &self.no_proxy),
+        ]
+        .into_iter()
+        .flat_map(|(key, value)| {
+            let value = value.clone().unwrap_or_default();
+            [
+                (key.to_string(), value.clone()),
+                (key.to_uppercase(), value),
+            ]
+        })
+        .collect()
+    }
+}
+
+/// Additional nix settings of the store container
+#[derive(Clone, Debug, Deserialize, Serialize, Default, PartialEq, Eq)]
+pub struct StoreConfig {
+    /// Binary caches which are used in addition to cache.nixos.org and codchi.cachix.org
+    #[serde(default)]
+    pub substituters: Vec<String>,
+
+    /// Public keys of the additional binary caches
+    #[serde(default)]
+    pub trusted_public_keys: Vec<String>,
+
+    /// Path to a netrc file on the host used to authenticate against binary caches
+    pub netrc_file: Option<String>,
+
+    /// Access tokens for fetching flake inputs from private repositories (`HOST=TOKEN`)
+    #[serde(default)]
+    pub access_tokens: Vec<String>,
+}
+
+impl StoreConfig {
+    /// Render as `nix.conf` which is included by the store's nix configuration. `netrc_path` is
+    /// the path of the netrc file inside the store.
+    pub fn to_nix_conf(&self, netrc_path: Option<&str>) -> String {
+        let mut conf = String::new();
+        if !self.substituters.is_empty() {
+            conf.push_str(&format!(
+                "extra-substituters = {}\n",
+                self.substituters.join(" ")
+            ));
+        }
+        if !self.trusted_public_keys.is_empty() {
+            conf.push_str(&format!(
+                "extra-trusted-public-keys = {}\n",
+                self.trusted_public_keys.join(" ")
+            ));
+        }
+        if let Some(netrc_path) = netrc_path {
+            conf.push_str(&format!("netrc-file = {netrc_path}\n"));
+        }
+        if !self.access_tokens.is_empty() {
+            conf.push_str(&format!(
+                "access-tokens = {}\n",
+                self.access_tokens.join(" ")
+            ));
+        }
+        conf
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
@@ -93,6 +213,10 @@ impl ConfigMut {
         self.doc["tray"]["autostart"] = value(autostart);
     }
 
+    pub fn data_dir(&mut self, dir: &str) {
+        self.doc["data_dir"] = value(dir);
+    }
+
     #[cfg(target_os = "windows")]
     pub fn vcxsrv_enable(&mut self, enable: bool) {
         self.doc["vcxsrv"]["enable"] = value(enable);
@@ -119,6 +243,66 @@ mod tests {
         assert_eq!(Ok(Default::default()), result);
     }
 
+    #[test]
+    fn store_cfg_renders_nix_conf() {
+        use crate::config::StoreConfig;
+        let cfg = toml_edit::de::from_str::<CodchiConfig>(
+            r#"
+[store]
+substituters = ["https://cache.example.com", "http://localhost:5000"]
+trusted_public_keys = ["cache.example.com-1:abc="]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.store,
+            StoreConfig {
+                substituters: vec![
+                    "https://cache.example.com".to_string(),
+                    "http://localhost:5000".to_string()
+                ],
+                trusted_public_keys: vec!["cache.example.com-1:abc=".to_string()],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            cfg.store.to_nix_conf(Some("/data/store/netrc")),
+            "extra-substituters = https://cache.example.com http://localhost:5000
+extra-trusted-public-keys = cache.example.com-1:abc=
+netrc-file = /data/store/netrc
+"
+        );
+    }
+
+    #[test]
+    fn network_cfg_proxy_env() {
+        let cfg = toml_edit::de::from_str::<CodchiConfig>(
+            r#"
+[network]
+https_proxy = "http://proxy.example.com:3128"
+no_proxy = "localhost,.example.com"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            cfg.network.proxy_env(),
+            vec![
+                ("http_proxy".to_string(), "".to_string()),
+                ("HTTP_PROXY".to_string(), "".to_string()),
+                (
+                    "https_proxy".to_string(),
+                    "http://proxy.example.com:3128".to_string()
+                ),
+                (
+                    "HTTPS_PROXY".to_string(),
+                    "http://proxy.example.com:3128".to_string()
+                ),
+                ("no_proxy".to_string(), "localhost,.example.com".to_string()),
+                ("NO_PROXY".to_string(), "localhost,.example.com".to_string()),
+            ]
+        );
+    }
+
     #[cfg(target_os = "windows")]
     #[test]
     fn partial_cfg_deserializes() {
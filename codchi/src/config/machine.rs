@@ -1,6 +1,6 @@
 use super::*;
-use crate::cli::ModuleName;
-use crate::consts::{host, ToPath, MACHINE_PREFIX};
+use crate::cli::{GpuPassthrough, ModuleName};
+use crate::consts::{self, host, ToPath, MACHINE_PREFIX};
 use crate::util::{PathExt, Required};
 use anyhow::anyhow;
 use std::path;
@@ -21,6 +21,41 @@ pub struct MachineConfig {
 
     #[serde(default)]
     pub secrets: HashMap<String, String>,
+
+    /// The `codchi_driver` flake url this machine was last built with. Used to detect machines
+    /// which lag behind the installed codchi version.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver_url: Option<String>,
+
+    /// Which GPUs are passed through from the host (Linux only)
+    #[serde(default)]
+    #[serde_as(as = "DisplayFromStr")]
+    pub gpu: GpuPassthrough,
+
+    /// Whether the host's Wayland socket is passed through (Linux only)
+    #[serde(default)]
+    pub wayland: bool,
+
+    /// Whether the host's PipeWire socket is passed through (Linux only)
+    #[serde(default)]
+    pub pipewire: bool,
+
+    /// Whether the host's PulseAudio socket is passed through (Linux only)
+    #[serde(default)]
+    pub pulseaudio: bool,
+
+    /// Whether the host's SSH agent (`$SSH_AUTH_SOCK`) is passed through (Linux only)
+    #[serde(default)]
+    pub ssh_agent: bool,
+
+    /// Whether the host's gpg-agent (extra socket) is passed through and the public keyring is
+    /// imported on start (Linux only)
+    #[serde(default)]
+    pub gpg_agent: bool,
+
+    /// Whether the host's git `user.name` and `user.email` are copied into the machine on start
+    #[serde(default)]
+    pub git_identity: bool,
 }
 
 pub enum ConfigResult {
@@ -39,9 +74,25 @@ impl MachineConfig {
             nixpkgs_from: Default::default(),
             modules: Default::default(),
             secrets: Default::default(),
+            driver_url: Default::default(),
+            gpu: Default::default(),
+            wayland: false,
+            pipewire: false,
+            pulseaudio: false,
+            ssh_agent: false,
+            gpg_agent: false,
+            git_identity: false,
         }
     }
 
+    /// Whether this machine was built with a different driver than the installed codchi provides.
+    /// `None` if it's unknown, because the machine was built before codchi recorded the driver.
+    pub fn driver_outdated(&self) -> Option<bool> {
+        self.driver_url
+            .as_deref()
+            .map(|url| url != consts::CODCHI_FLAKE_URL)
+    }
+
     pub fn find(name: &str) -> Result<ConfigResult> {
         let path = host::DIR_CONFIG.join_machine(name).join("config.json");
         match fs::metadata(&path) {
@@ -13,6 +13,8 @@ pub struct MachineStatus {
     #[serde_as(as = "serde_with::DisplayFromStr")]
     pub status: ConfigStatus,
     pub running: bool,
+    /// `None` if the machine was built before codchi recorded its driver
+    pub driver_outdated: Option<bool>,
 }
 
 pub type ModLsOutput = Vec<Mod>;
@@ -22,3 +24,106 @@ pub struct Mod {
     pub url: String,
     pub flake_module: String,
 }
+
+pub type UpgradeOutput = Vec<MachineUpgrade>;
+#[derive(Serialize, Deserialize)]
+pub struct MachineUpgrade {
+    pub name: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+/// Host devices passed through to a code machine
+#[derive(Serialize, Deserialize)]
+pub struct PassthroughOutput {
+    pub gpu: String,
+    pub wayland: bool,
+    pub pipewire: bool,
+    pub pulseaudio: bool,
+    pub ssh_agent: bool,
+    pub gpg_agent: bool,
+    pub git_identity: bool,
+}
+
+/// Result of `codchi gc`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GcOutput {
+    pub dry_run: bool,
+    /// Generations which were (or would be) deleted per machine
+    pub generations: Vec<GcGenerations>,
+    pub deleted_paths: usize,
+    pub freed_bytes: u64,
+    /// Size of the store after the garbage collection
+    pub store_size: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GcGenerations {
+    pub machine: String,
+    pub generations: Vec<u32>,
+}
+
+/// Result of `codchi store du`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoreUsage {
+    /// Paths which are only used by a single machine
+    pub machines: Vec<StoreUsageEntry>,
+    /// Paths which are used by multiple machines or the store itself
+    pub shared: StoreUsageEntry,
+    /// Paths which aren't referenced at all and would be deleted by `codchi gc`
+    pub garbage: StoreUsageEntry,
+    pub total: StoreUsageEntry,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoreUsageEntry {
+    pub name: String,
+    pub paths: usize,
+    pub bytes: u64,
+}
+
+/// Result of `codchi store verify`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoreVerifyOutput {
+    /// Only the closure of this machine was verified
+    pub machine: Option<String>,
+    pub repair: bool,
+    pub checked_paths: usize,
+    /// Paths whose contents don't match their hash. They were repaired if `repair` is set and
+    /// there are no `errors`.
+    pub corrupted_paths: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+impl StoreVerifyOutput {
+    /// No errors occurred and all corrupted paths (if any) were repaired
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty() && (self.repair || self.corrupted_paths.is_empty())
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StoreNixConfig {
+    pub substituters: Vec<String>,
+    pub trusted_public_keys: Vec<String>,
+    pub netrc_file: Option<String>,
+    /// Only the hosts, not the tokens
+    pub access_tokens: Vec<String>,
+}
+
+pub type DoctorOutput = Vec<DoctorCheck>;
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DoctorCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+    pub remediation: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
@@ -74,10 +74,22 @@ pub mod host {
             .unwrap_or_else(env::temp_dir)
             .join(APP_NAME)
     });
+    /// SSH keys of the machines. Unlike DIR_CONFIG and DIR_DATA, this isn't mounted into any
+    /// container.
+    pub static DIR_SSH: LazyLock<PathBuf> =
+        LazyLock::new(|| BASE_DIR.home_dir().join(".ssh").join(APP_NAME));
     pub static LOGFILE_STORE: LazyLock<PathBuf> = LazyLock::new(|| DIR_DATA.join("log/store.log"));
+    pub static LOGFILE_GC: LazyLock<PathBuf> = LazyLock::new(|| DIR_DATA.join("log/gc.log"));
     pub fn machine_log(name: &str) -> PathBuf {
         DIR_DATA.join(format!("log/machine-{name}.log"))
     }
+    /// Saved logs of each build / garbage collection
+    pub static DIR_BUILD_LOGS: LazyLock<PathBuf> = LazyLock::new(|| DIR_DATA.join("log/builds"));
+    pub static DIR_STORE_BUILD_LOGS: LazyLock<PathBuf> =
+        LazyLock::new(|| DIR_BUILD_LOGS.join("store"));
+    pub fn machine_build_logs(name: &str) -> PathBuf {
+        DIR_BUILD_LOGS.join(format!("machine-{name}"))
+    }
 }
 
 pub mod store {
@@ -0,0 +1,247 @@
+use crate::cli::CopyLocation;
+use crate::consts::{self, ToPath};
+use crate::logging::set_progress_status;
+use crate::platform::*;
+use crate::util::{human_size, LinuxPath};
+use anyhow::{bail, Context, Result};
+use std::{
+    fs,
+    io::{Read, Write},
+    path::Path,
+    process::{Child, Command, Stdio},
+};
+
+/// Extracts a tar from stdin to `$1`. If `$1` is an existing directory, `$2` is extracted into
+/// it. Otherwise `$2` is renamed to `$1` (like `cp`).
+const GUEST_EXTRACT: &str = r#"
+set -e
+dst="$1"; name="$2"
+if [ -d "$dst" ]; then
+    tar --no-same-owner -C "$dst" -xf -
+else
+    parent="$(dirname "$dst")"
+    mkdir -p "$parent"
+    tmp="$(mktemp -d -p "$parent" .codchi-cp.XXXXXX)"
+    trap 'rm -rf "$tmp"' EXIT
+    tar --no-same-owner -C "$tmp" -xf -
+    mv -T "$tmp/$name" "$dst"
+fi
+"#;
+
+/// Writes a tar of `$1` to stdout
+const GUEST_ARCHIVE: &str = r#"
+set -e
+test -e "$1" || { echo "'$1' doesn't exist." >&2; exit 1; }
+tar -C "$(dirname "$1")" -cf - "$(basename "$1")"
+"#;
+
+/// Copy files or directories between the host and a code machine. Files inside the machine are
+/// owned by the default user (or root). A stopped machine is started for the copy and stopped
+/// again afterwards.
+pub fn copy(src: &CopyLocation, dst: &CopyLocation, root: bool) -> Result<()> {
+    let user = if root {
+        LinuxUser::Root
+    } else {
+        LinuxUser::Default
+    };
+    match (src, dst) {
+        (CopyLocation::Host(src), CopyLocation::Machine { name, path }) => {
+            with_running_machine(name, |machine| push(machine, src, &guest_path(path), user))?;
+            log::info!("Copied {src:?} to {name}:{path}.");
+        }
+        (CopyLocation::Machine { name, path }, CopyLocation::Host(dst)) => {
+            with_running_machine(name, |machine| pull(machine, &guest_path(path), dst, user))?;
+            log::info!("Copied {name}:{path} to {dst:?}.");
+        }
+        (CopyLocation::Host(_), CopyLocation::Host(_)) => {
+            bail!("Either SRC or DEST must be inside a code machine (`<MACHINE_NAME>:<PATH>`).")
+        }
+        (CopyLocation::Machine { .. }, CopyLocation::Machine { .. }) => {
+            bail!("Copying between two code machines is not supported. Copy to the host first.")
+        }
+    }
+    Ok(())
+}
+
+/// Run `f` while the machine is running. If it was stopped before, it is stopped again
+/// afterwards, even if `f` failed.
+fn with_running_machine<F>(name: &str, f: F) -> Result<()>
+where
+    F: FnOnce(&Machine) -> Result<()>,
+{
+    let machine = Machine::by_name(name, true)?;
+    if machine.config_status == ConfigStatus::NotInstalled
+        || machine.platform_status == PlatformStatus::NotInstalled
+    {
+        bail!("Machine {name} wasn't installed yet. Install with `codchi rebuild {name}`.");
+    }
+    let was_stopped = machine.platform_status == PlatformStatus::Stopped;
+    set_progress_status(format!("Starting {name}..."));
+    machine.start()?;
+
+    let result = f(&machine);
+
+    if was_stopped {
+        set_progress_status(format!("Stopping {name}..."));
+        let stopped = machine.stop(false);
+        match (&result, stopped) {
+            (Ok(()), stopped) => stopped.with_context(|| format!("Failed stopping {name}."))?,
+            // the failed copy is the more important error
+            (Err(_), Err(err)) => log::warn!("Failed stopping {name}: {err}"),
+            (Err(_), Ok(())) => {}
+        }
+    }
+    result
+}
+
+/// Relative paths are relative to the home of the default user
+fn guest_path(path: &str) -> LinuxPath {
+    let path = match path.trim_end_matches('/') {
+        "" if path.starts_with('/') => "/",
+        path => path,
+    };
+    if path.starts_with('/') {
+        LinuxPath(path.to_string())
+    } else if path.is_empty() {
+        consts::user::DEFAULT_HOME.clone()
+    } else {
+        consts::user::DEFAULT_HOME.join_str(path)
+    }
+}
+
+fn push(machine: &Machine, src: &Path, dst: &LinuxPath, user: LinuxUser) -> Result<()> {
+    let src = src
+        .canonicalize()
+        .with_context(|| format!("{src:?} doesn't exist."))?;
+    let (Some(parent), Some(name)) = (src.parent(), src.file_name()) else {
+        bail!("Can't copy {src:?}.");
+    };
+    let name = name.to_string_lossy().to_string();
+
+    let mut host_tar = Command::new("tar")
+        .arg("-C")
+        .arg(parent)
+        .args(["-cf", "-", &name])
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed running `tar` on the host.")?;
+    let mut guest_cmd: Command = machine
+        .cmd()
+        .run("bash", &["-c", GUEST_EXTRACT, "codchi-cp", &dst.0, &name])
+        .with_user(user)
+        .into();
+    let mut guest_tar = guest_cmd.stdin(Stdio::piped()).spawn()?;
+
+    transfer(
+        &format!("{src:?}"),
+        host_size(&src),
+        &mut host_tar,
+        &mut guest_tar,
+    )
+    .with_context(|| format!("Failed copying {src:?} to {}:{dst}.", machine.config.name))
+}
+
+fn pull(machine: &Machine, src: &LinuxPath, dst: &Path, user: LinuxUser) -> Result<()> {
+    let name = src.0.rsplit('/').next().unwrap_or(&src.0).to_string();
+    // Without login shell (unlike `run`), so nothing else is written to stdout
+    let size = machine
+        .cmd()
+        .raw("du", &["-sb", &src.0])
+        .with_user(user.clone())
+        .output_utf8_ok()
+        .ok()
+        .and_then(|out| out.split_whitespace().next()?.parse().ok());
+
+    // Like `cp`, copy into `dst` if it is a directory. Otherwise rename to `dst`.
+    let (target_dir, rename) = if dst.is_dir() {
+        (dst.to_path_buf(), None)
+    } else {
+        let parent = match dst.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => Path::new(".").to_path_buf(),
+        };
+        fs::create_dir_all(&parent)?;
+        let tmp = parent.join(format!(".codchi-cp-{}", rand::random::<u32>()));
+        fs::create_dir(&tmp)?;
+        (tmp.clone(), Some(tmp))
+    };
+
+    let result = (|| {
+        let mut guest_cmd: Command = machine
+            .cmd()
+            .raw("bash", &["-c", GUEST_ARCHIVE, "codchi-cp", &src.0])
+            .with_user(user)
+            .into();
+        let mut guest_tar = guest_cmd.stdout(Stdio::piped()).spawn()?;
+        let mut host_tar = Command::new("tar")
+            .arg("-C")
+            .arg(&target_dir)
+            .args(["-xf", "-"])
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("Failed running `tar` on the host.")?;
+        transfer(
+            &format!("{}:{src}", machine.config.name),
+            size,
+            &mut guest_tar,
+            &mut host_tar,
+        )?;
+        if let Some(tmp) = &rename {
+            if dst.exists() {
+                fs::remove_file(dst).with_context(|| format!("Failed overwriting {dst:?}."))?;
+            }
+            fs::rename(tmp.join(&name), dst)?;
+        }
+        anyhow::Ok(())
+    })();
+    if let Some(tmp) = rename {
+        let _ = fs::remove_dir_all(tmp);
+    }
+    result.with_context(|| format!("Failed copying {}:{src} to {dst:?}.", machine.config.name))
+}
+
+/// Pipe the stdout of `from` into the stdin of `to` while showing the progress
+fn transfer(what: &str, total: Option<u64>, from: &mut Child, to: &mut Child) -> Result<()> {
+    let mut reader = from.stdout.take().context("Missing stdout.")?;
+    let mut writer = to.stdin.take().context("Missing stdin.")?;
+    let total = total.map(|total| format!(" / {}", human_size(total)));
+    let mut copied: u64 = 0;
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+        let read = reader.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&buf[..read])?;
+        copied += read as u64;
+        set_progress_status(format!(
+            "Copying {what}: {}{}",
+            human_size(copied),
+            total.as_deref().unwrap_or_default()
+        ));
+    }
+    drop(writer);
+
+    let from_status = from.wait()?;
+    let to_status = to.wait()?;
+    if !from_status.success() || !to_status.success() {
+        bail!("`tar` failed ({from_status}, {to_status}).");
+    }
+    Ok(())
+}
+
+/// Total size of all files in `path`
+fn host_size(path: &Path) -> Option<u64> {
+    let meta = fs::symlink_metadata(path).ok()?;
+    if meta.is_dir() {
+        Some(
+            fs::read_dir(path)
+                .ok()?
+                .flatten()
+                .filter_map(|entry| host_size(&entry.path()))
+                .sum(),
+        )
+    } else {
+        Some(meta.len())
+    }
+}
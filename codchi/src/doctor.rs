@@ -0,0 +1,172 @@
+use crate::config::{CheckStatus, CodchiConfig, DoctorCheck, MachineConfig};
+use crate::consts::{host, MACHINE_PREFIX};
+use crate::logging::set_progress_status;
+use crate::platform::{self, nix::NixDriver, *};
+use crate::util::human_size;
+use std::fs;
+use sysinfo::Disks;
+
+/// Free space in `DIR_DATA` below which a warning is shown
+const MIN_FREE_SPACE_WARN: u64 = 10 * 1024 * 1024 * 1024;
+/// Free space in `DIR_DATA` below which the check fails
+const MIN_FREE_SPACE_FAIL: u64 = 2 * 1024 * 1024 * 1024;
+
+impl DoctorCheck {
+    pub fn pass(name: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Pass,
+            message: message.into(),
+            remediation: None,
+        }
+    }
+
+    pub fn warn(
+        name: impl Into<String>,
+        message: impl Into<String>,
+        remediation: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Warn,
+            message: message.into(),
+            remediation: Some(remediation.into()),
+        }
+    }
+
+    pub fn fail(
+        name: impl Into<String>,
+        message: impl Into<String>,
+        remediation: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            status: CheckStatus::Fail,
+            message: message.into(),
+            remediation: Some(remediation.into()),
+        }
+    }
+}
+
+/// Run all checks. Checks never fail, problems are reported as `CheckStatus::Fail` instead. This
+/// must work without a working store, so the store is only used if it is already running.
+pub fn run() -> Vec<DoctorCheck> {
+    set_progress_status("Checking host setup...");
+    let mut checks = platform::doctor::checks();
+    checks.push(check_disk_space());
+    checks.extend(check_machine_configs());
+    if platform::doctor::store_running() {
+        checks.extend(check_tls());
+    }
+    checks
+}
+
+fn check_disk_space() -> DoctorCheck {
+    const NAME: &str = "Disk space";
+    let data_dir = host::DIR_DATA
+        .canonicalize()
+        .unwrap_or(host::DIR_DATA.clone());
+    let disks = Disks::new_with_refreshed_list();
+    let Some(disk) = disks
+        .iter()
+        .filter(|disk| data_dir.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+    else {
+        return DoctorCheck::warn(
+            NAME,
+            format!("Couldn't find the disk of {data_dir:?}."),
+            "Make sure there are at least 10 GiB free on this disk.",
+        );
+    };
+    let free = disk.available_space();
+    let message = format!(
+        "{} free on {:?} (contains {data_dir:?})",
+        human_size(free),
+        disk.mount_point()
+    );
+    let remediation = "Free up space or run `codchi gc` to delete unused store paths.";
+    if free < MIN_FREE_SPACE_FAIL {
+        DoctorCheck::fail(NAME, message, remediation)
+    } else if free < MIN_FREE_SPACE_WARN {
+        DoctorCheck::warn(NAME, message, remediation)
+    } else {
+        DoctorCheck::pass(NAME, message)
+    }
+}
+
+fn check_machine_configs() -> Vec<DoctorCheck> {
+    let Ok(entries) = fs::read_dir(host::DIR_CONFIG.join(MACHINE_PREFIX)) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let check_name = format!("Machine '{name}'");
+            let config_file = entry.path().join("config.json");
+            match MachineConfig::open(&name, false) {
+                Ok((_, Some(_))) => DoctorCheck::pass(check_name, "Config is valid"),
+                Ok((_, None)) => DoctorCheck::warn(
+                    check_name,
+                    format!("{config_file:?} is missing."),
+                    format!("Remove leftovers with `codchi delete {name}`."),
+                ),
+                Err(err) => DoctorCheck::fail(
+                    check_name,
+                    format!("Failed parsing {config_file:?}: {err:#}"),
+                    format!("Fix the file or remove the machine with `codchi delete {name}`."),
+                ),
+            }
+        })
+        .collect()
+}
+
+/// Check that all binary caches are reachable through the configured proxies and CA
+/// certificates.
+fn check_tls() -> Vec<DoctorCheck> {
+    set_progress_status("Checking connection to binary caches...");
+    let network = &CodchiConfig::get().network;
+    let remediation = if network.ca_certificates.is_empty() {
+        "If you are behind a proxy which intercepts TLS, add its CA certificate to \
+`network.ca_certificates` in codchi's config."
+    } else {
+        "Make sure the files in `network.ca_certificates` contain the CA certificate of your \
+proxy in PEM format."
+    };
+
+    let store = Driver::store();
+    let substituters = match store.nix_config() {
+        Ok(cfg) => cfg.substituters,
+        Err(err) => {
+            return vec![DoctorCheck::fail(
+                "TLS",
+                format!("Failed reading the nix configuration of the store: {err:#}"),
+                "Check the `[store]` section in codchi's config.",
+            )]
+        }
+    };
+    substituters
+        .into_iter()
+        .map(|url| {
+            let name = format!("TLS: {url}");
+            match store.cmd().ping_substituter(&url) {
+                Ok(()) => DoctorCheck::pass(name, "Reachable"),
+                Err(nix::Error::InvalidRemoteSSLOrSSH) => {
+                    DoctorCheck::fail(name, "The TLS certificate was not accepted.", remediation)
+                }
+                Err(nix::Error::NetworkUnavailable) => DoctorCheck::warn(
+                    name,
+                    "Couldn't resolve host.",
+                    "Check your network connection and the proxy settings in `[network]` of \
+codchi's config.",
+                ),
+                Err(err) => DoctorCheck::fail(
+                    name,
+                    format!("{err}"),
+                    "Check the proxy settings in `[network]` of codchi's config.",
+                ),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,110 @@
+use super::nix::{self, Activity, LogItem, LogResult};
+use crate::util::{format_timestamp, store_path_base, PathExt};
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static BUILD_LOG: Mutex<Option<File>> = Mutex::new(None);
+
+/// A build log which is being recorded. Recording stops when this is dropped. Only one build log
+/// can be recorded at a time.
+pub struct BuildLog(PathBuf);
+
+impl BuildLog {
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for BuildLog {
+    fn drop(&mut self) {
+        *BUILD_LOG.lock().unwrap() = None;
+        log::debug!("Saved log to {:?}", self.0);
+    }
+}
+
+/// Save every line passed to [super::log_progress] (e.g. the raw JSON log of nix) in a new file
+/// in `dir`, named after the current time. Fails if another build log is being recorded.
+pub fn record_build_log(dir: &Path) -> Result<BuildLog> {
+    let mut slot = BUILD_LOG.lock().unwrap();
+    if slot.is_some() {
+        bail!("Another build log is already being recorded.");
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let timestamp = format_timestamp(now);
+    dir.get_or_create()?;
+    // Builds in the same second get a suffix, which sorts after the first one
+    for n in 0.. {
+        let path = match n {
+            0 => dir.join(format!("{timestamp}.log")),
+            n => dir.join(format!("{timestamp}_{n}.log")),
+        };
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => {
+                *slot = Some(file);
+                return Ok(BuildLog(path));
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed creating build log {path:?}"))
+            }
+        }
+    }
+    unreachable!()
+}
+
+pub(super) fn record_line(line: &str) {
+    if let Some(file) = BUILD_LOG.lock().unwrap().as_mut() {
+        if let Err(err) = writeln!(file, "{line}") {
+            log::trace!("Failed writing build log: {err}");
+        }
+    }
+}
+
+/// Turns the lines of a saved build log back into readable messages
+#[derive(Default)]
+pub struct LogFormatter {
+    builds: HashMap<i64, String>,
+}
+
+impl LogFormatter {
+    pub fn format(&mut self, line: &str) -> Option<String> {
+        match nix::parse_line(line).ok()? {
+            LogItem::OutputLine(line) => Some(line),
+            LogItem::Msg { msg, .. } => Some(msg),
+            LogItem::Start {
+                id, text, activity, ..
+            } => {
+                if let Activity::Build { path, .. } = activity {
+                    self.builds.insert(id, store_path_base(&path));
+                }
+                Some(text).filter(|text| !text.is_empty())
+            }
+            LogItem::Result {
+                id,
+                result: LogResult::BuildLogLine { line } | LogResult::PostBuildLogLine { line },
+            } => Some(match self.builds.get(&id) {
+                Some(name) => format!("{name}> {line}"),
+                None => line,
+            }),
+            LogItem::NddRestart { restart, max } => Some(format!(
+                "Nix deadlocked and was restarted ({restart}/{max})"
+            )),
+            LogItem::Stop { id } => {
+                self.builds.remove(&id);
+                None
+            }
+            LogItem::Result { .. } | LogItem::UnknownItem(_) => None,
+        }
+    }
+}
@@ -10,9 +10,11 @@ use indicatif_log_bridge::LogWrapper;
 use log::{Level, LevelFilter};
 use progress::{Progress, ROOT_BAR};
 
+mod build_log;
 mod nix;
 pub mod output;
 mod progress;
+pub use build_log::{record_build_log, BuildLog, LogFormatter};
 pub use output::*;
 
 fn progress() -> &'static Arc<RwLock<Option<Progress>>> {
@@ -64,12 +66,16 @@ pub fn set_progress_status<M: Into<Cow<'static, str>>>(status: M) {
 }
 
 pub fn log_progress(fallback_target: &str, fallback_level: Level, msg: &str) {
+    build_log::record_line(msg);
     with_progress(|progress| progress.log(fallback_target, fallback_level, msg));
 }
 
+/// Remove the progress bar and log a summary of what nix did meanwhile
 pub fn hide_progress() {
-    let mut progress = progress().write().unwrap();
-    drop(progress.take());
+    let progress = progress().write().unwrap().take();
+    if let Some(stats) = progress.map(Progress::into_stats) {
+        stats.log_summary();
+    }
 }
 
 pub fn with_suspended_progress<R>(prompt: impl Fn() -> R) -> R {
@@ -183,6 +183,11 @@ pub enum LogItem {
         id: i64,
         result: LogResult,
     },
+    /// Sent by `ndd` when it restarts a deadlocked nix
+    NddRestart {
+        restart: u64,
+        max: u64,
+    },
     OutputLine(String),
     UnknownItem(Value),
 }
@@ -253,6 +258,10 @@ pub fn parse_log_item(val: &Value) -> Option<LogItem> {
         "stop" => Some(Stop {
             id: val.get("id")?.as_i64()?,
         }),
+        "ndd_restart" => Some(NddRestart {
+            restart: val.get("restart")?.as_u64()?,
+            max: val.get("max")?.as_u64()?,
+        }),
         "result" => {
             let result_type: ResultType = val.get("type")?.as_i64()?.try_into().ok()?;
             let fields = val
@@ -1,6 +1,11 @@
 use std::{fmt::Display, io::stdout};
 
-use crate::config::{MachineModules, MachineStatus, Mod, ModLsOutput, StatusOutput};
+use crate::config::{
+    CheckStatus, DoctorCheck, DoctorOutput, GcOutput, MachineConfig, MachineModules, MachineStatus,
+    MachineUpgrade, Mod, ModLsOutput, PassthroughOutput, StatusOutput, StoreNixConfig, StoreUsage,
+    StoreVerifyOutput, UpgradeOutput,
+};
+use crate::util::human_size;
 use itertools::Itertools;
 use serde::Serialize;
 
@@ -26,6 +31,7 @@ impl CodchiOutput<StatusOutput> for Vec<Machine> {
                 name: m.config.name.clone(),
                 status: m.config_status.clone(),
                 running: m.platform_status == crate::platform::PlatformStatus::Running,
+                driver_outdated: m.config.driver_outdated(),
             })
             .collect()
     }
@@ -37,6 +43,7 @@ impl CodchiOutput<StatusOutput> for Vec<Machine> {
             Cell::new("Machine"),
             Cell::new("Status"),
             Cell::new("Running?"),
+            Cell::new("Driver"),
         ]);
 
         for machine in out.iter() {
@@ -51,6 +58,11 @@ impl CodchiOutput<StatusOutput> for Vec<Machine> {
                     ConfigStatus::UpToDate => Cell::new("Up to date").fg(Color::Green),
                 },
                 Cell::new(if machine.running { "✅" } else { "❌" }),
+                match machine.driver_outdated {
+                    Some(true) => Cell::new("Outdated").fg(Color::Yellow),
+                    Some(false) => Cell::new("Current").fg(Color::Green),
+                    None => Cell::new("Unknown"),
+                },
             ]);
         }
 
@@ -91,3 +103,286 @@ impl CodchiOutput<ModLsOutput> for MachineModules {
         table
     }
 }
+
+impl CodchiOutput<UpgradeOutput> for Vec<(Machine, anyhow::Result<()>)> {
+    fn to_output(&self) -> UpgradeOutput {
+        self.iter()
+            .map(|(m, result)| MachineUpgrade {
+                name: m.config.name.clone(),
+                success: result.is_ok(),
+                error: result.as_ref().err().map(|err| format!("{err:#}")),
+            })
+            .collect()
+    }
+
+    fn human_output(out: UpgradeOutput) -> impl Display {
+        use comfy_table::*;
+        let mut table = Table::new();
+        table
+            .load_preset(presets::UTF8_FULL)
+            .set_header(vec![Cell::new("Machine"), Cell::new("Upgrade")]);
+
+        for m in out {
+            table.add_row(vec![
+                Cell::new(&m.name),
+                match m.error {
+                    None => Cell::new("Success").fg(Color::Green),
+                    Some(err) => Cell::new(format!("Failed: {err}")).fg(Color::Red),
+                },
+            ]);
+        }
+        table
+    }
+}
+
+impl CodchiOutput<PassthroughOutput> for MachineConfig {
+    fn to_output(&self) -> PassthroughOutput {
+        PassthroughOutput {
+            gpu: self.gpu.to_string(),
+            wayland: self.wayland,
+            pipewire: self.pipewire,
+            pulseaudio: self.pulseaudio,
+            ssh_agent: self.ssh_agent,
+            gpg_agent: self.gpg_agent,
+            git_identity: self.git_identity,
+        }
+    }
+
+    fn human_output(out: PassthroughOutput) -> impl Display {
+        use comfy_table::*;
+        let mut table = Table::new();
+        table
+            .load_preset(presets::UTF8_FULL)
+            .set_header(vec![Cell::new("Device"), Cell::new("Setting")]);
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+        table.add_row(vec![Cell::new("GPU"), Cell::new(out.gpu)]);
+        table.add_row(vec![Cell::new("Wayland"), Cell::new(on_off(out.wayland))]);
+        table.add_row(vec![Cell::new("PipeWire"), Cell::new(on_off(out.pipewire))]);
+        table.add_row(vec![
+            Cell::new("PulseAudio"),
+            Cell::new(on_off(out.pulseaudio)),
+        ]);
+        table.add_row(vec![
+            Cell::new("SSH agent"),
+            Cell::new(on_off(out.ssh_agent)),
+        ]);
+        table.add_row(vec![
+            Cell::new("GPG agent"),
+            Cell::new(on_off(out.gpg_agent)),
+        ]);
+        table.add_row(vec![
+            Cell::new("Git identity"),
+            Cell::new(on_off(out.git_identity)),
+        ]);
+        table
+    }
+}
+
+impl CodchiOutput<StoreNixConfig> for StoreNixConfig {
+    fn to_output(&self) -> StoreNixConfig {
+        self.clone()
+    }
+
+    fn human_output(out: StoreNixConfig) -> impl Display {
+        use comfy_table::*;
+        let mut table = Table::new();
+        table
+            .load_preset(presets::UTF8_FULL)
+            .set_header(vec![Cell::new("Setting"), Cell::new("Value")]);
+
+        table.add_row(vec![
+            Cell::new("Substituters"),
+            Cell::new(out.substituters.join("\n")),
+        ]);
+        table.add_row(vec![
+            Cell::new("Trusted public keys"),
+            Cell::new(out.trusted_public_keys.join("\n")),
+        ]);
+        table.add_row(vec![
+            Cell::new("Netrc file"),
+            Cell::new(out.netrc_file.unwrap_or_else(|| "-".to_string())),
+        ]);
+        table.add_row(vec![
+            Cell::new("Access tokens"),
+            Cell::new(out.access_tokens.join("\n")),
+        ]);
+        table
+    }
+}
+
+impl CodchiOutput<GcOutput> for GcOutput {
+    fn to_output(&self) -> GcOutput {
+        self.clone()
+    }
+
+    fn human_output(out: GcOutput) -> impl Display {
+        use comfy_table::*;
+        let mut output = String::new();
+        if out
+            .generations
+            .iter()
+            .any(|gens| !gens.generations.is_empty())
+        {
+            let mut table = Table::new();
+            table.load_preset(presets::UTF8_FULL).set_header(vec![
+                Cell::new("Machine"),
+                Cell::new(if out.dry_run {
+                    "Generations to delete"
+                } else {
+                    "Deleted generations"
+                }),
+            ]);
+            for gens in out.generations {
+                table.add_row(vec![
+                    Cell::new(gens.machine),
+                    Cell::new(if gens.generations.is_empty() {
+                        "-".to_string()
+                    } else {
+                        gens.generations.iter().join(", ")
+                    }),
+                ]);
+            }
+            output.push_str(&format!(
+                "{table}
+"
+            ));
+        }
+        if out.dry_run {
+            output.push_str(&format!(
+                "Would delete {} store paths and free {}. The store would use {} afterwards.",
+                out.deleted_paths,
+                human_size(out.freed_bytes),
+                human_size(out.store_size)
+            ));
+        } else {
+            output.push_str(&format!(
+                "Deleted {} store paths and freed {}. The store now uses {}.",
+                out.deleted_paths,
+                human_size(out.freed_bytes),
+                human_size(out.store_size)
+            ));
+        }
+        output
+    }
+}
+
+impl CodchiOutput<StoreUsage> for StoreUsage {
+    fn to_output(&self) -> StoreUsage {
+        self.clone()
+    }
+
+    fn human_output(out: StoreUsage) -> impl Display {
+        use comfy_table::*;
+        let mut table = Table::new();
+        table.load_preset(presets::UTF8_FULL).set_header(vec![
+            Cell::new("Closure"),
+            Cell::new("Paths"),
+            Cell::new("Size"),
+        ]);
+        let row = |entry: crate::config::StoreUsageEntry, name: Cell| {
+            vec![
+                name,
+                Cell::new(entry.paths).set_alignment(CellAlignment::Right),
+                Cell::new(human_size(entry.bytes)).set_alignment(CellAlignment::Right),
+            ]
+        };
+        for machine in out.machines {
+            let name = Cell::new(&machine.name);
+            table.add_row(row(machine, name));
+        }
+        table.add_row(row(out.shared, Cell::new("(shared)").fg(Color::Blue)));
+        table.add_row(row(out.garbage, Cell::new("(garbage)").fg(Color::Yellow)));
+        table.add_row(row(
+            out.total,
+            Cell::new("Total").add_attribute(Attribute::Bold),
+        ));
+        table
+    }
+}
+
+impl CodchiOutput<StoreVerifyOutput> for StoreVerifyOutput {
+    fn to_output(&self) -> StoreVerifyOutput {
+        self.clone()
+    }
+
+    fn human_output(out: StoreVerifyOutput) -> impl Display {
+        use comfy_table::*;
+        let scope = match &out.machine {
+            Some(machine) => format!("store paths of {machine}"),
+            None => "store paths".to_string(),
+        };
+        if out.corrupted_paths.is_empty() && out.errors.is_empty() {
+            return format!("All {} {scope} are valid.", out.checked_paths);
+        }
+        let repaired = out.repair && out.errors.is_empty();
+        let mut output = String::new();
+        if !out.corrupted_paths.is_empty() {
+            let mut table = Table::new();
+            table
+                .load_preset(presets::UTF8_FULL)
+                .set_header(vec![Cell::new("Store path"), Cell::new("Status")]);
+            for path in &out.corrupted_paths {
+                table.add_row(vec![
+                    Cell::new(path),
+                    if repaired {
+                        Cell::new("Repaired").fg(Color::Green)
+                    } else {
+                        Cell::new("Corrupted").fg(Color::Red)
+                    },
+                ]);
+            }
+            output.push_str(&format!(
+                "{table}
+"
+            ));
+        }
+        for error in &out.errors {
+            output.push_str(&format!(
+                "{error}
+"
+            ));
+        }
+        output.push_str(&format!(
+            "Checked {} {scope}, {} of them were corrupted.",
+            out.checked_paths,
+            out.corrupted_paths.len()
+        ));
+        if !out.repair && !out.corrupted_paths.is_empty() {
+            output.push_str(" Use `--repair` to fix them.");
+        }
+        output
+    }
+}
+
+impl CodchiOutput<DoctorOutput> for Vec<DoctorCheck> {
+    fn to_output(&self) -> DoctorOutput {
+        self.clone()
+    }
+
+    fn human_output(out: DoctorOutput) -> impl Display {
+        use comfy_table::*;
+        let mut table = Table::new();
+        table.load_preset(presets::UTF8_FULL).set_header(vec![
+            Cell::new("Check"),
+            Cell::new("Status"),
+            Cell::new("Details"),
+        ]);
+
+        for check in out {
+            let details = match check.remediation {
+                Some(remediation) => format!("{}\n→ {remediation}", check.message),
+                None => check.message,
+            };
+            table.add_row(vec![
+                Cell::new(&check.name),
+                match check.status {
+                    CheckStatus::Pass => Cell::new("Pass").fg(Color::Green),
+                    CheckStatus::Warn => Cell::new("Warn").fg(Color::Yellow),
+                    CheckStatus::Fail => Cell::new("Fail").fg(Color::Red),
+                },
+                Cell::new(details),
+            ]);
+        }
+        table
+    }
+}
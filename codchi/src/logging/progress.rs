@@ -1,11 +1,16 @@
-use super::nix::{self, Activity, ActivityType, LogItem, LogResult};
-use crate::util::store_path_base;
+use super::nix::{self, Activity, ActivityType, LogItem, LogResult, Verbosity};
+use crate::util::{human_duration, human_size, store_path_base};
 use console::style;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use itertools::Itertools;
 use log::Level;
 use number_prefix::NumberPrefix;
-use std::{borrow::Cow, collections::HashMap, sync::LazyLock, time::Duration};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
 use throttle::Throttle;
 
 pub static ROOT_BAR: LazyLock<MultiProgress> = LazyLock::new(|| {
@@ -28,6 +33,7 @@ pub enum NixActivity {
     Build {
         name: String,
         phase: Option<String>,
+        started: Instant,
     },
     Unpack {
         done: u64,
@@ -35,11 +41,73 @@ pub enum NixActivity {
     Download {
         done: u64,
     },
+    /// Fetching a store path from a binary cache. `failed` is set if nix reported an error about
+    /// the path meanwhile.
+    Substitute {
+        path: String,
+        failed: bool,
+    },
+}
+
+/// What nix did while the progress was shown
+#[derive(Debug, Clone)]
+pub struct BuildStats {
+    started: Instant,
+    /// Finished builds and how long they took
+    builds: Vec<(String, Duration)>,
+    /// Store paths fetched from a binary cache
+    substituted: u64,
+    downloaded_bytes: u64,
+}
+
+impl BuildStats {
+    /// Number of slowest builds which are shown in the summary
+    const SLOWEST_BUILDS: usize = 5;
+
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            builds: Vec::new(),
+            substituted: 0,
+            downloaded_bytes: 0,
+        }
+    }
+
+    /// Log a summary, if nix built or substituted anything
+    pub fn log_summary(&self) {
+        let built = self.builds.len() as u64;
+        let total = built + self.substituted;
+        if total == 0 {
+            return;
+        }
+        log::info!(
+            "Finished after {}: built {built}, substituted {} ({}% from cache), downloaded {}.",
+            human_duration(self.started.elapsed()),
+            self.substituted,
+            self.substituted * 100 / total,
+            human_size(self.downloaded_bytes),
+        );
+        if !self.builds.is_empty() {
+            let slowest = self
+                .builds
+                .iter()
+                .sorted_by_key(|(_, duration)| std::cmp::Reverse(*duration))
+                .take(Self::SLOWEST_BUILDS)
+                .map(|(name, duration)| format!("{name} ({})", human_duration(*duration)))
+                .join(", ");
+            log::info!("Slowest builds: {slowest}");
+        }
+    }
 }
 
 // #[derive(Debug, Clone)]
 pub struct Progress {
     activities: HashMap<i64, NixActivity>,
+    /// Restarts of a deadlocked nix by `ndd` (restart, max restarts)
+    retry: Option<(u64, u64)>,
+    stats: BuildStats,
+    /// Start of the first download of the current nix operation, to estimate the throughput
+    dl_started: Option<Instant>,
     status_bar: ProgressBar,
     throttle: Throttle,
 }
@@ -62,11 +130,18 @@ impl Progress {
 
         Self {
             activities: HashMap::default(),
+            retry: None,
+            stats: BuildStats::new(),
+            dl_started: None,
             status_bar: ROOT_BAR.add(status),
             throttle: Throttle::new(fps, 1),
         }
     }
 
+    pub fn into_stats(mut self) -> BuildStats {
+        std::mem::replace(&mut self.stats, BuildStats::new())
+    }
+
     pub fn set_status<M>(&self, msg: M)
     where
         M: Into<Cow<'static, str>>,
@@ -95,6 +170,13 @@ impl Progress {
                 log::warn!("Unknown message from nix: {}", line);
             }
             Ok(LogItem::Msg { level, msg }) => {
+                if level <= Verbosity::Warn {
+                    for activity in self.activities.values_mut() {
+                        if let NixActivity::Substitute { path, failed } = activity {
+                            *failed |= msg.contains(path.as_str());
+                        }
+                    }
+                }
                 let msg = msg.lines().join("\r\n");
                 log::log!(target: "nix", level.into(), "{msg}")
             }
@@ -108,6 +190,7 @@ impl Progress {
                     // root activity with total expected dl / unpack
                     Activity::Realise => {
                         self.activities.clear();
+                        self.dl_started = None;
                         self.activities.insert(
                             id,
                             NixActivity::Root {
@@ -130,8 +213,14 @@ impl Progress {
                     // individual build activities
                     Activity::Build { path, .. } => {
                         let name = store_path_base(&path);
-                        self.activities
-                            .insert(id, NixActivity::Build { name, phase: None });
+                        self.activities.insert(
+                            id,
+                            NixActivity::Build {
+                                name,
+                                phase: None,
+                                started: Instant::now(),
+                            },
+                        );
                         log::log!(target: "nix", fallback_level, "{text}");
                     }
                     // unpack activity count
@@ -147,14 +236,45 @@ impl Progress {
                     Activity::FileTransfer { .. } => {
                         self.activities
                             .insert(id, NixActivity::Download { done: 0 });
+                        self.dl_started.get_or_insert_with(Instant::now);
                         // log::log!(target: "nix", fallback_level, "{text}");
                     }
+                    Activity::Substitute { path, .. } => {
+                        self.activities.insert(
+                            id,
+                            NixActivity::Substitute {
+                                path,
+                                failed: false,
+                            },
+                        );
+                    }
                     _else => {}
                 }
             }
+            Ok(LogItem::NddRestart { restart, max }) => {
+                // the activities of the killed nix process are gone
+                self.activities.clear();
+                self.dl_started = None;
+                self.retry = Some((restart, max));
+            }
             Ok(LogItem::Stop { id }) => {
-                if let Some(NixActivity::Root { .. }) = self.activities.get(&id) {
-                    self.activities.clear();
+                match self.activities.get(&id) {
+                    Some(NixActivity::Root { .. }) => {
+                        self.activities.clear();
+                        self.dl_started = None;
+                    }
+                    Some(NixActivity::Build { name, started, .. }) => {
+                        self.stats.builds.push((name.clone(), started.elapsed()));
+                    }
+                    Some(NixActivity::Download { done }) => {
+                        self.stats.downloaded_bytes += done;
+                    }
+                    // nix doesn't report whether an activity succeeded, so a substitution counts
+                    // unless nix complained about its path
+                    Some(NixActivity::Substitute { failed: false, .. }) => {
+                        self.stats.substituted += 1;
+                    }
+                    _else => {}
                 };
                 // self.activities.remove(&id);
             }
@@ -163,7 +283,7 @@ impl Progress {
                 if let Some(activity) = self.activities.get_mut(&id) {
                     match result {
                         BuildLogLine { line } => {
-                            if let NixActivity::Build { name, phase } = activity {
+                            if let NixActivity::Build { name, phase, .. } = activity {
                                 let phase = phase
                                     .to_owned()
                                     .map(|phase| format!(" ({phase})"))
@@ -293,7 +413,41 @@ impl Progress {
                 (done + acc.0, expected + acc.1)
             });
 
+        let dl_done: u64 = this
+            .activities
+            .values()
+            .map(|a| match a {
+                NixActivity::Download { done } => *done,
+                _else => 0,
+            })
+            .sum();
+        let dl_expected: u64 = this
+            .activities
+            .values()
+            .map(|a| match a {
+                NixActivity::Root {
+                    dl_bytes_expected, ..
+                } => *dl_bytes_expected,
+                _else => 0,
+            })
+            .sum();
+        // remaining bytes at the throughput since the first download
+        let eta = this.dl_started.and_then(|started| {
+            let elapsed = started.elapsed().as_secs_f64();
+            let remaining = dl_expected.saturating_sub(dl_done);
+            if elapsed < 1.0 || dl_done == 0 || remaining == 0 {
+                return None;
+            }
+            let throughput = dl_done as f64 / elapsed;
+            Some(format!(
+                "ETA {}",
+                human_duration(Duration::from_secs_f64(remaining as f64 / throughput))
+            ))
+        });
+
         let prefices = [
+            this.retry
+                .map(|(restart, max)| format!("retrying ({restart}/{max})")),
             fmt("building", false, build_done, build_expected),
             fmt(
                 "unpacking",
@@ -316,26 +470,8 @@ impl Progress {
                     })
                     .sum(),
             ),
-            fmt(
-                "downloading",
-                true,
-                this.activities
-                    .values()
-                    .map(|a| match a {
-                        NixActivity::Download { done } => *done,
-                        _else => 0,
-                    })
-                    .sum(),
-                this.activities
-                    .values()
-                    .map(|a| match a {
-                        NixActivity::Root {
-                            dl_bytes_expected, ..
-                        } => *dl_bytes_expected,
-                        _else => 0,
-                    })
-                    .sum(),
-            ),
+            fmt("downloading", true, dl_done, dl_expected),
+            eta,
         ]
         .iter()
         .flatten()
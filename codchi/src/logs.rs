@@ -0,0 +1,130 @@
+use crate::config::MachineConfig;
+use crate::consts::host;
+use crate::logging::LogFormatter;
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+/// How often a followed log is checked for new lines
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Saved build logs in `dir`, newest first
+fn build_logs(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut logs = fs::read_dir(dir)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<io::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .collect::<Vec<_>>();
+    // file names are timestamps
+    logs.sort();
+    logs.reverse();
+    Ok(logs)
+}
+
+/// Select a log file:
+/// * `machine` - the machine or `None` for the store
+/// * `build` - the N-th latest build log (1 is the latest). Defaults to the latest build of a
+///   machine and the store's log.
+/// * `init` - the init / runtime log of the machine
+fn select_log(machine: Option<&str>, build: Option<usize>, init: bool) -> Result<PathBuf> {
+    let (dir, what) = match machine {
+        Some(name) if init => return Ok(host::machine_log(name)),
+        Some(name) => (host::machine_build_logs(name), format!("builds of {name}")),
+        None => match build {
+            None => return Ok(host::LOGFILE_STORE.clone()),
+            Some(_) => (
+                host::DIR_STORE_BUILD_LOGS.clone(),
+                "garbage collections".to_string(),
+            ),
+        },
+    };
+    let n = build.unwrap_or(1);
+    if n == 0 {
+        bail!("Build numbers start at 1 (the latest build).");
+    }
+    let logs = build_logs(&dir)?;
+    if logs.is_empty() {
+        bail!("No logs of {what} were saved yet.");
+    }
+    logs.get(n - 1)
+        .cloned()
+        .ok_or_else(|| anyhow!("Only {count} logs of {what} are saved.", count = logs.len()))
+}
+
+/// Print a log, rendering saved nix JSON lines as readable messages unless `raw` is set. With
+/// `follow`, wait for new lines until interrupted.
+pub fn show(
+    machine: Option<&str>,
+    build: Option<usize>,
+    init: bool,
+    follow: bool,
+    raw: bool,
+) -> Result<()> {
+    let path = select_log(machine, build, init)?;
+    log::debug!("Showing log {path:?}");
+    let file = File::open(&path).with_context(|| format!("Failed opening log {path:?}"))?;
+    let mut reader = BufReader::new(file);
+    let mut formatter = LogFormatter::default();
+    let mut out = io::stdout().lock();
+    let mut line = String::new();
+    loop {
+        // a followed log might end with an incomplete line which is still being written
+        if reader.read_line(&mut line)? == 0 || (follow && !line.ends_with('\n')) {
+            if !follow {
+                break;
+            }
+            out.flush()?;
+            thread::sleep(FOLLOW_INTERVAL);
+            continue;
+        }
+        let content = line.trim_end_matches(['\r', '\n']);
+        if raw {
+            writeln!(out, "{content}")?;
+        } else if let Some(msg) = formatter.format(content) {
+            writeln!(out, "{msg}")?;
+        }
+        line.clear();
+    }
+    Ok(())
+}
+
+/// Delete all but the newest `keep` build logs of each machine and the store, and all build
+/// logs of machines which don't exist anymore. Returns the number of deleted logs.
+pub fn prune(keep: usize) -> Result<usize> {
+    if !host::DIR_BUILD_LOGS.exists() {
+        return Ok(0);
+    }
+    let machines = MachineConfig::list()?
+        .into_iter()
+        .map(|machine| host::machine_build_logs(&machine.name))
+        .collect::<Vec<_>>();
+    let mut deleted = 0;
+    for entry in fs::read_dir(&*host::DIR_BUILD_LOGS)? {
+        let dir = entry?.path();
+        if !dir.is_dir() {
+            continue;
+        }
+        let logs = build_logs(&dir)?;
+        if *dir != *host::DIR_STORE_BUILD_LOGS && !machines.contains(&dir) {
+            log::debug!("Deleting logs of removed machine {dir:?}");
+            fs::remove_dir_all(&dir).with_context(|| format!("Failed deleting {dir:?}"))?;
+            deleted += logs.len();
+            continue;
+        }
+        for log in logs.iter().skip(keep) {
+            log::debug!("Deleting log {log:?}");
+            fs::remove_file(log).with_context(|| format!("Failed deleting {log:?}"))?;
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
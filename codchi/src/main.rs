@@ -7,10 +7,10 @@ use crate::{
     platform::{Driver, Machine, Store},
 };
 use clap::{CommandFactory, Parser};
-use config::{git_url::GitUrl, CodchiConfig, MachineConfig};
+use config::{git_url::GitUrl, CheckStatus, CodchiConfig, MachineConfig};
 use console::style;
 use log::Level;
-use logging::{set_progress_status, CodchiOutput};
+use logging::{hide_progress, set_progress_status, CodchiOutput};
 use platform::{store_debug_shell, ConfigStatus, Host, MachineDriver};
 use std::{
     env,
@@ -19,12 +19,17 @@ use std::{
 };
 use util::{ResultExt, UtilExt};
 
+pub mod bundle;
 pub mod cli;
 pub mod config;
 pub mod consts;
+pub mod cp;
+pub mod doctor;
 pub mod logging;
+pub mod logs;
 pub mod module;
 pub mod platform;
+pub mod ssh;
 pub mod tray;
 pub mod util;
 
@@ -67,7 +72,7 @@ Thank you kindly!"#
         eprintln!("{}", style(msg).red());
     }));
 
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
 
     // process immediate commands
     if let Some(Cmd::Completion { shell }) = &cli.command {
@@ -81,6 +86,19 @@ Thank you kindly!"#
 
     // preload config
     let cfg = CodchiConfig::get();
+    cli.offline |= cfg.offline;
+    // bundles are meant for air-gapped hosts
+    if matches!(
+        cli.command,
+        Some(Cmd::Bundle(cli::BundleCmd::Install { .. }))
+    ) {
+        cli.offline = true;
+    }
+
+    // OFFLINE, DEBUG etc. are read from CLI_ARGS, also by commands which don't need the store
+    CLI_ARGS
+        .set(cli.clone())
+        .expect("Only main is allowed to set CLI_ARGS.");
 
     if !matches!(cli.command, Some(Cmd::Tray {})) && cfg.tray.autostart {
         Driver::host()
@@ -99,21 +117,61 @@ Thank you kindly!"#
             }
             exit(0);
         }
+        // must work without a working store
+        Some(Cmd::Doctor {}) => {
+            let checks = progress_scope! { doctor::run() };
+            checks.print(cli.json);
+            let failed = checks
+                .iter()
+                .filter(|check| check.status == CheckStatus::Fail)
+                .count();
+            exit(if failed > 0 { 1 } else { 0 });
+        }
+        Some(Cmd::Logs {
+            name,
+            build,
+            init,
+            store: _,
+            follow,
+            raw,
+            prune,
+        }) => {
+            match prune {
+                Some(keep) => {
+                    let deleted = logs::prune(*keep)?;
+                    log::info!("Deleted {deleted} build logs.");
+                }
+                None => logs::show(name.as_deref(), *build, *init, *follow, *raw)?,
+            }
+            exit(0);
+        }
         Some(Cmd::Store(store)) => match store {
             cli::StoreCmd::Debug => store_debug_shell()?,
+            cli::StoreCmd::Config | cli::StoreCmd::Du | cli::StoreCmd::Verify { .. } => {}
             #[cfg(target_os = "windows")]
             cli::StoreCmd::Recover => {
                 platform::store_recover()?;
                 exit(0);
             }
+            #[cfg(target_os = "linux")]
+            cli::StoreCmd::Move { target } => {
+                progress_scope! { platform::store_move(target) }?;
+                exit(0);
+            }
+            #[cfg(target_os = "linux")]
+            cli::StoreCmd::Repair => {
+                progress_scope! { platform::store_repair() }?;
+                exit(0);
+            }
+            #[cfg(target_os = "linux")]
+            cli::StoreCmd::Reset => {
+                progress_scope! { platform::store_reset() }?;
+                exit(0);
+            }
         },
         _ => {}
     }
 
-    CLI_ARGS
-        .set(cli.clone())
-        .expect("Only main is allowed to set CLI_ARGS.");
-
     let _ = Driver::store();
 
     // all other commands
@@ -193,7 +251,115 @@ Thank you kindly!"#
             Machine::by_name(name, true)?.build(*no_update)?;
             log::info!("Machine {name} rebuilt successfully!");
         }
-        Cmd::Exec { name, cmd } => Machine::by_name(name, true)?.exec(cmd)?,
+        Cmd::Bundle(cmd) => match cmd {
+            cli::BundleCmd::Create { name, target_file } => {
+                progress_scope! {
+                    bundle::create(name, target_file)?;
+                }
+            }
+            cli::BundleCmd::Install { bundle_file } => {
+                let machine = progress_scope! { bundle::install(bundle_file) }?;
+                let name = &machine.config.name;
+                log::info!("Machine '{name}' is ready! Use `codchi exec {name}` to start it.");
+            }
+        },
+        Cmd::Upgrade { all, machines } => {
+            let selected = if machines.is_empty() {
+                Machine::list(true)?
+                    .into_iter()
+                    .filter(|m| {
+                        m.config_status != ConfigStatus::NotInstalled
+                            && (*all || m.config.driver_outdated() != Some(false))
+                    })
+                    .collect()
+            } else {
+                machines
+                    .iter()
+                    .map(|name| Machine::by_name(name, false))
+                    .collect::<anyhow::Result<Vec<_>>>()?
+            };
+            if selected.is_empty() {
+                log::info!("All machines already use the driver of this codchi version.");
+            } else {
+                let report = selected
+                    .into_iter()
+                    .map(|machine| {
+                        let result = machine.upgrade().inspect_err(|err| {
+                            hide_progress();
+                            log::error!("Failed upgrading {}: {err:#}", machine.config.name);
+                        });
+                        (machine, result)
+                    })
+                    .collect::<Vec<_>>();
+                report.print(cli.json);
+                let failed = report.iter().filter(|(_, result)| result.is_err()).count();
+                if failed > 0 {
+                    anyhow::bail!("Failed upgrading {failed} of {} machines.", report.len());
+                }
+            }
+        }
+        Cmd::Passthrough {
+            name,
+            gpu,
+            wayland,
+            pipewire,
+            pulseaudio,
+            ssh_agent,
+            gpg_agent,
+            git_identity,
+        } => {
+            if gpu.is_none()
+                && wayland.is_none()
+                && pipewire.is_none()
+                && pulseaudio.is_none()
+                && ssh_agent.is_none()
+                && gpg_agent.is_none()
+                && git_identity.is_none()
+            {
+                let (_, cfg) = MachineConfig::open_existing(name, false)?;
+                cfg.print(cli.json);
+            } else {
+                let (lock, mut cfg) = MachineConfig::open_existing(name, true)?;
+                if let Some(gpu) = gpu {
+                    cfg.gpu = gpu.clone();
+                }
+                cfg.wayland = wayland.unwrap_or(cfg.wayland);
+                cfg.pipewire = pipewire.unwrap_or(cfg.pipewire);
+                cfg.pulseaudio = pulseaudio.unwrap_or(cfg.pulseaudio);
+                cfg.ssh_agent = ssh_agent.unwrap_or(cfg.ssh_agent);
+                cfg.gpg_agent = gpg_agent.unwrap_or(cfg.gpg_agent);
+                cfg.git_identity = git_identity.unwrap_or(cfg.git_identity);
+                cfg.write(lock)?;
+                let machine = Machine::by_name(name, true)?;
+                let devices_changed = gpu.is_some()
+                    || wayland.is_some()
+                    || pipewire.is_some()
+                    || pulseaudio.is_some()
+                    || ssh_agent.is_some()
+                    || gpg_agent.is_some();
+                if cfg!(target_os = "windows") {
+                    if devices_changed {
+                        log::warn!(
+                            "Device passthrough is managed by WSL. These settings have no effect \
+on Windows."
+                        );
+                    }
+                } else if devices_changed
+                    && machine.platform_status != platform::PlatformStatus::NotInstalled
+                {
+                    machine.update_devices()?;
+                }
+                log::info!("Updated passthrough settings of machine '{name}'.");
+            }
+        }
+        Cmd::Exec { name, options, cmd } => Machine::by_name(name, true)?.exec(cmd, options)?,
+        Cmd::Cp { root, src, dst } => {
+            progress_scope! {
+                cp::copy(src, dst, *root)?;
+            }
+        }
+        Cmd::SshConfig {} => print!("{}", progress_scope! { ssh::config() }?),
+        Cmd::SshProxy { name } => ssh::proxy(name)?,
         Cmd::Delete {
             name,
             i_am_really_sure,
@@ -243,14 +409,45 @@ Thank you kindly!"#
                 alert_dirty(module::delete(name, module_name)?)
             }
         },
+        Cmd::GC {
+            policy: true,
+            dry_run,
+            ..
+        } => match progress_scope! { Driver::store().gc_policy(true, *dry_run) }? {
+            Some(output) => output.print(cli.json),
+            None => log::info!("No retention policy is configured in codchi's config ([gc])."),
+        },
         Cmd::GC {
             delete_old,
             all,
+            dry_run,
             machines,
-        } => Driver::store().gc(delete_old.map(|x| x.unwrap_or_default()), *all, machines)?,
+            ..
+        } => progress_scope! {
+            Driver::store().gc(
+                delete_old.map(|x| x.unwrap_or_default()),
+                *all,
+                machines,
+                *dry_run,
+            )
+        }?
+        .print(cli.json),
         Cmd::Tray {} => tray::run()?,
         Cmd::Completion { .. } => unreachable!(),
         Cmd::Tar { .. } => unreachable!(),
+        Cmd::Doctor {} => unreachable!(),
+        Cmd::Logs { .. } => unreachable!(),
+        Cmd::Store(cli::StoreCmd::Config) => Driver::store().nix_config()?.print(cli.json),
+        Cmd::Store(cli::StoreCmd::Du) => {
+            progress_scope! { Driver::store().usage() }?.print(cli.json)
+        }
+        Cmd::Store(cli::StoreCmd::Verify { repair, machine }) => {
+            let out = progress_scope! { Driver::store().verify(*repair, machine.as_deref()) }?;
+            out.print(cli.json);
+            if !out.is_valid() {
+                anyhow::bail!("The store isn't valid.");
+            }
+        }
         Cmd::Store(_) => unreachable!(),
     }
     if CodchiConfig::get().tray.autostart {
@@ -284,9 +481,16 @@ fn alert_dirty(machine: Machine) {
                 machine.config.name, machine.config.name
             );
         }
-        ConfigStatus::UpToDate => {
+        ConfigStatus::UpToDate if machine.config.driver_outdated() != Some(true) => {
             println!("Everything up to date!");
         }
+        ConfigStatus::UpToDate => {}
+    }
+    if machine.config.driver_outdated() == Some(true) {
+        println!(
+            "{} uses an outdated codchi driver. Upgrade with `codchi upgrade {}`",
+            machine.config.name, machine.config.name
+        );
     }
 }
 
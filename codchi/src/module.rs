@@ -1,4 +1,6 @@
-use crate::cli::{InputOptions, ModuleAttrPath, ModuleName, NixpkgsLocation, RelativePath};
+use crate::cli::{
+    InputOptions, ModuleAttrPath, ModuleName, NixpkgsLocation, RelativePath, OFFLINE,
+};
 use crate::config::git_url::{GitUrl, Scheme};
 use crate::consts;
 use crate::consts::user::DEFAULT_HOME;
@@ -52,6 +54,14 @@ pub fn init(
                     },
                     modules,
                     secrets: Default::default(),
+                    driver_url: None,
+                    gpu: Default::default(),
+                    wayland: false,
+                    pipewire: false,
+                    pulseaudio: false,
+                    ssh_agent: false,
+                    gpg_agent: false,
+                    git_identity: false,
                 }
             }
         };
@@ -589,16 +599,39 @@ pub fn clone(
         log::warn!("Ignoring option `--no-build`.");
         input_options.no_build = true;
     }
-    if git_url.scheme != Scheme::Http && git_url.scheme != Scheme::Https {
-        bail!("Only HTTP(S) is available at the moment.")
+    let ssh = git_url.scheme == Scheme::Ssh;
+    if !ssh && git_url.scheme != Scheme::Http && git_url.scheme != Scheme::Https {
+        bail!("Only HTTP(S) and SSH urls are available at the moment.")
+    }
+    if *OFFLINE {
+        bail!("Cloning a repository is not possible in offline mode.")
+    }
+    if ssh {
+        if cfg!(target_os = "windows") {
+            bail!(
+                "Cloning via SSH requires SSH agent forwarding, which isn't available on Windows."
+            )
+        }
+        if std::env::var_os("SSH_AUTH_SOCK").is_none() {
+            bail!(
+                "Cloning via SSH requires a running SSH agent on the host, but $SSH_AUTH_SOCK is \
+                not set."
+            )
+        }
     }
 
-    let mut machine = init(
-        machine_name,
-        Some(git_url.clone()),
-        &input_options,
-        module_paths,
-    )?;
+    let mut machine = if ssh {
+        // The store can't fetch the repository via SSH. Therefore start with a base machine, clone
+        // the repository with the forwarded SSH agent and add its modules from the local checkout.
+        init(machine_name, None, &InputOptions::default(), &vec![])?
+    } else {
+        init(
+            machine_name,
+            Some(git_url.clone()),
+            &input_options,
+            module_paths,
+        )?
+    };
     machine.build(true)?;
     progress_scope! {
         set_progress_status("Cloning git repository...");
@@ -611,16 +644,33 @@ pub fn clone(
                 .unwrap_or_else(|| petname::petname(1, "-")
                                 .expect("Failed to generate random name"))
         });
-        let git_url = format!(
-            "{scheme}://{auth}{host}/{repo}",
-            auth = input_options
-                .auth
-                .map(|t| format!("{t}@"))
-                .unwrap_or_default(),
-            scheme = git_url.scheme,
-            host = git_url.host.unwrap(),
-            repo = git_url.path,
-        );
+        let (git, git_url) = if ssh {
+            (
+                "nix shell nixpkgs#git nixpkgs#openssh -c git",
+                format!(
+                    "ssh://{user}{host}{port}/{repo}",
+                    user = git_url.user.map(|u| format!("{u}@")).unwrap_or_default(),
+                    host = git_url.host.context("Host missing.")?,
+                    port = git_url.port.map(|p| format!(":{p}")).unwrap_or_default(),
+                    repo = git_url.path.trim_start_matches('/'),
+                ),
+            )
+        } else {
+            (
+                "nix run nixpkgs#git --",
+                format!(
+                    "{scheme}://{auth}{host}/{repo}",
+                    auth = input_options
+                        .auth
+                        .clone()
+                        .map(|t| format!("{t}@"))
+                        .unwrap_or_default(),
+                    scheme = git_url.scheme,
+                    host = git_url.host.unwrap(),
+                    repo = git_url.path,
+                ),
+            )
+        };
 
         let mut git_opts = vec![];
         if let Some(depth) = depth {
@@ -637,42 +687,81 @@ pub fn clone(
         }
         let git_opts = git_opts.join(" ");
 
-        let cmd = if let Some(branch) = input_options.branch {
+        let cmd = if let Some(branch) = &input_options.branch {
             format!(
-                r#"nix run nixpkgs#git -- clone {git_opts} -b "{branch}" "{git_url}" \
+                r#"{git} clone {git_opts} -b "{branch}" "{git_url}" \
                 "{target_dir}""#
             )
-        } else if let Some(tag) = input_options.tag {
+        } else if let Some(tag) = &input_options.tag {
             format!(
                 r#"
-nix run nixpkgs#git -- clone {git_opts} "{git_url}" "{target_dir}"
+{git} clone {git_opts} "{git_url}" "{target_dir}"
 cd "{target_dir}"
-nix run nixpkgs#git -- fetch --tags
-nix run nixpkgs#git -- checkout tags/{tag}
+{git} fetch --tags
+{git} checkout tags/{tag}
 "#
             )
-        } else if let Some(commit) = input_options.commit {
+        } else if let Some(commit) = &input_options.commit {
             format!(
                 r#"
-nix run nixpkgs#git -- clone {git_opts} "{git_url}" "{target_dir}"
+{git} clone {git_opts} "{git_url}" "{target_dir}"
 cd "{target_dir}"
-nix run nixpkgs#git -- checkout {commit}
+{git} checkout {commit}
 "#
             )
         } else {
-            format!(r#"nix run nixpkgs#git -- clone {git_opts} "{git_url}" "{target_dir}""#)
+            format!(r#"{git} clone {git_opts} "{git_url}" "{target_dir}""#)
         };
+        // Unknown hosts can't be confirmed interactively
+        let cmd = if ssh {
+            format!("export GIT_SSH_COMMAND='ssh -o StrictHostKeyChecking=accept-new'\n{cmd}")
+        } else {
+            cmd
+        };
+        if ssh {
+            // Forward the SSH agent only for cloning, without enabling it in the machine's config.
+            // Sockets are passed through when the machine starts, so restart it.
+            machine.config.ssh_agent = true;
+            machine.stop(false)?;
+        }
         machine.start()?;
-        machine
+        let cloned = machine
             .cmd()
             .script(cmd)
             .with_user(platform::LinuxUser::Default)
             .with_cwd(DEFAULT_HOME.clone())
             .output_ok_streaming(channel().1, |line| {
                 log_progress("git clone", log::Level::Info, &line)
-            })?;
+            });
+        if ssh {
+            // The next start removes the SSH agent socket again
+            machine.config.ssh_agent = false;
+            machine.stop(false)?;
+        }
+        cloned?;
 
-        if !keep_remote {
+        if ssh {
+            if *keep_remote {
+                log::warn!(
+                    "Ignoring option `--keep-remote`, because the store can't fetch SSH \
+                    repositories."
+                );
+            }
+            let local_url = FlakeUrl::<Empty> {
+                location: FlakeLocation::Local { path: target_dir.clone() },
+                commit: None,
+                r#ref: None,
+                flake_attr: PhantomData,
+            }
+            .to_git_url();
+            let local_options = InputOptions {
+                dont_prompt: input_options.dont_prompt,
+                use_nixpkgs: input_options.use_nixpkgs.clone(),
+                ..Default::default()
+            };
+            machine = add(&machine.config.name, local_url, &local_options, module_paths)?;
+            machine.build(true)?;
+        } else if !keep_remote {
             let (lock, mut cfg) = MachineConfig::open_existing(&machine.config.name, true)?;
             for url in cfg.modules.values_mut() {
                 url.location = FlakeLocation::Local{ path: target_dir.clone() }
@@ -162,3 +162,158 @@ impl CommandExt for LinuxCommandBuilder {
         Ok(child)
     }
 }
+
+/// Arguments for `bash` to run `cmd` inside a code machine. By default, all arguments are passed
+/// as-is, without word splitting, globbing or variable expansion. With `shell`, the arguments are
+/// joined by spaces and interpreted by bash (e.g. for pipes or globs). An empty `cmd` opens a
+/// login shell.
+pub fn bash_exec_args(cmd: &[&str], shell: bool, cwd: Option<&LinuxPath>) -> Vec<String> {
+    let mut script = String::new();
+    let mut args = vec!["-c".to_string()];
+    if cwd.is_some() {
+        script.push_str(r#"cd -- "$1" || exit 1; shift; "#);
+    }
+    script.push_str(if cmd.is_empty() {
+        r#"exec "${SHELL:-bash}" -l"#
+    } else if shell {
+        r#"eval "$*""#
+    } else {
+        r#"exec "$@""#
+    });
+    args.push(script);
+    // $0
+    args.push("codchi-exec".to_string());
+    if let Some(cwd) = cwd {
+        args.push(cwd.0.clone());
+    }
+    args.extend(cmd.iter().map(|arg| arg.to_string()));
+    args
+}
+
+/// Quote `arg` for a POSIX shell, so that it is passed as a single argument without any expansion
+#[cfg(any(target_os = "windows", test))]
+pub fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn run_bash(cmd: &[&str], shell: bool) -> String {
+        let output = Command::new("bash")
+            .args(bash_exec_args(cmd, shell, None))
+            .output()
+            .expect("Failed running bash");
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn exec_preserves_argv() {
+        let matrix = [
+            "a b",
+            "",
+            " ",
+            "'",
+            "\"",
+            "\"a b\"",
+            "'a b'",
+            "*",
+            "~",
+            "$HOME",
+            "${HOME}",
+            "$(id)",
+            "`id`",
+            "\\",
+            "\\n",
+            "a\nb",
+            "\t",
+            ";",
+            "&&",
+            "|",
+            "> file",
+            "--",
+            "-e",
+            "!",
+            "#comment",
+            "ünïcödé",
+        ];
+        for arg in matrix {
+            let out = run_bash(&["printf", "%s\\0", arg], false);
+            assert_eq!(out, format!("{arg}\0"), "Argument {arg:?} was modified");
+        }
+
+        let out = run_bash(&[&["printf", "%s\\0"], &matrix[..]].concat(), false);
+        let args: Vec<&str> = out.strip_suffix('\0').unwrap().split('\0').collect();
+        assert_eq!(args, matrix);
+    }
+
+    #[test]
+    fn shell_quote_survives_reparsing() {
+        // like wsl.exe, which passes the command to the default shell of the user
+        let args = [
+            "a b", "'a b'", "\"a b\"", "it's", "$HOME", "`id`", "\\", "", "*",
+        ];
+        let script = [&["printf", "%s\\0"], &args[..]]
+            .concat()
+            .iter()
+            .map(|arg| shell_quote(arg))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let output = Command::new("sh").args(["-c", &script]).output().unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let out = String::from_utf8(output.stdout).unwrap();
+        let parsed: Vec<&str> = out.strip_suffix('\0').unwrap().split('\0').collect();
+        assert_eq!(parsed, args);
+    }
+
+    #[test]
+    fn exec_shell_interprets_cmd() {
+        assert_eq!(
+            run_bash(&["echo", "$((1 + 1))", "|", "tr", "2", "3"], true),
+            "3\n"
+        );
+        assert_eq!(run_bash(&["echo 'a  b'"], true), "a  b\n");
+    }
+
+    #[test]
+    fn exec_changes_cwd() {
+        let dir = std::env::temp_dir().join("codchi exec 'cwd' $HOME");
+        std::fs::create_dir_all(&dir).unwrap();
+        let cwd = LinuxPath(dir.to_string_lossy().to_string());
+        for shell in [false, true] {
+            let output = Command::new("bash")
+                .args(bash_exec_args(&["pwd"], shell, Some(&cwd)))
+                .output()
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(output.stdout).unwrap(),
+                format!("{cwd}\n")
+            );
+        }
+
+        let missing = LinuxPath("/does/not/exist".to_string());
+        let status = Command::new("bash")
+            .args(bash_exec_args(&["pwd"], false, Some(&missing)))
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(1));
+        std::fs::remove_dir(dir).unwrap();
+    }
+
+    #[test]
+    fn exec_propagates_exit_code() {
+        let status = |cmd: &[&str], shell: bool| {
+            Command::new("bash")
+                .args(bash_exec_args(cmd, shell, None))
+                .status()
+                .unwrap()
+                .code()
+        };
+        assert_eq!(status(&["bash", "-c", "exit 42"], false), Some(42));
+        assert_eq!(status(&["exit", "42"], true), Some(42));
+        assert_eq!(status(&["false"], false), Some(1));
+    }
+}
@@ -213,10 +213,19 @@ Stderr:
 
     fn exec(&mut self) -> Result<()> {
         log::trace!("Execing command: {self:?}");
-        exit(self.spawn(OutputType::Inherit)?.wait()?.code().unwrap_or(1))
+        exit(exit_code(self.spawn(OutputType::Inherit)?.wait()?))
     }
 }
 
+/// Exit code like a shell would report it: 128 + the signal if the process was killed
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(1)
+}
+
 #[derive(Debug, Clone)]
 pub enum OutputType {
     Inherit,
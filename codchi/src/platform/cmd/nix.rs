@@ -1,7 +1,10 @@
 use std::time::Duration;
 
 use super::*;
-use crate::{cli::ModuleAttrPath, util::LinuxPath};
+use crate::{
+    cli::{ModuleAttrPath, OFFLINE},
+    util::LinuxPath,
+};
 use serde_json::Value;
 
 #[derive(Error, Debug)]
@@ -12,8 +15,11 @@ pub enum Error {
     #[error("The 'flake.lock' inside this repository is out of date. Please run 'nix flake lock' inside this repository, commit the newly generated lockfile and try again.")]
     LockOutOfDate,
 
-    // TODO: docs
-    #[error("SSL peer certificate or SSH remote key was not OK.")]
+    #[error(
+        "SSL peer certificate or SSH remote key was not OK. If you are behind a proxy which \
+intercepts TLS, add its CA certificate to `network.ca_certificates` in codchi's config. Run \
+`codchi doctor` to check the connection to all binary caches."
+    )]
     InvalidRemoteSSLOrSSH,
 
     #[error("Couldn't access repository. If the repository is private you need to provide the correct credentials. If it's a local repository, make sure it is checked out locally in the machine at the provided path. See `codchi init --help` on how to access private repositories.")]
@@ -22,6 +28,18 @@ pub enum Error {
     #[error("There is a file missing (maybe its wasn't added to git): {0}")]
     FileMissing(String),
 
+    #[error(
+        "Codchi is in offline mode but the following isn't available locally: {0}\nRun the \
+command again without `--offline` (and `offline = false` in codchi's config) to fetch it."
+    )]
+    NotCached(String),
+
+    #[error(
+        "Couldn't reach the network. If you don't have internet access, use `--offline` to \
+only use locally cached inputs."
+    )]
+    NetworkUnavailable,
+
     #[error("Nix command failed: {0}")]
     Command(super::cmd::Error),
 }
@@ -30,7 +48,15 @@ pub type Result<T> = std::result::Result<T, Error>;
 impl From<cmd::Error> for Error {
     fn from(err: cmd::Error) -> Self {
         if let cmd::Error::Other { stderr, .. } = &err {
-            if stderr.contains("SSL peer certificate or SSH remote key was not OK") {
+            if *OFFLINE
+                && (stderr.contains("offline mode") || stderr.contains("unable to download"))
+            {
+                Error::NotCached(stderr.lines().last().unwrap_or_default().to_owned())
+            } else if stderr.contains("Could not resolve host")
+                || stderr.contains("Couldn't resolve host name")
+            {
+                Error::NetworkUnavailable
+            } else if stderr.contains("SSL peer certificate or SSH remote key was not OK") {
                 Error::InvalidRemoteSSLOrSSH
             } else if
             // http / ssh
@@ -61,7 +87,7 @@ pub trait NixDriver: LinuxCommandTarget {
         let list_attr_names = |attr_path: &str| -> Result<Vec<String>> {
             let args = [
                 "eval",
-                "--refresh",
+                refresh_flag(),
                 "--no-write-lock-file",
                 "--json",
                 "--quiet",
@@ -102,7 +128,7 @@ pub trait NixDriver: LinuxCommandTarget {
         let args = [
             "flake",
             "metadata",
-            "--refresh",
+            refresh_flag(),
             "--json",
             "--no-write-lock-file",
             &self.quote_shell_arg(url),
@@ -120,18 +146,12 @@ pub trait NixDriver: LinuxCommandTarget {
     where
         T: for<'de> Deserialize<'de>,
     {
-        Ok(self
-            .run(
-                "nix",
-                &[
-                    "eval",
-                    &self.quote_shell_arg(&format!(".#{path}")),
-                    "--no-write-lock-file",
-                    "--json",
-                ],
-            )
-            .with_cwd(flake)
-            .output_json::<T>()?)
+        let attr = self.quote_shell_arg(&format!(".#{path}"));
+        let mut args = vec!["eval", &attr, "--no-write-lock-file", "--json"];
+        if *OFFLINE {
+            args.push("--offline");
+        }
+        Ok(self.run("nix", &args).with_cwd(flake).output_json::<T>()?)
     }
 
     fn ping_store(&self) -> bool {
@@ -139,6 +159,14 @@ pub trait NixDriver: LinuxCommandTarget {
             .wait_ok()
             .is_ok()
     }
+
+    /// Check that a binary cache is reachable (including its TLS certificate)
+    fn ping_substituter(&self, url: &str) -> Result<()> {
+        Ok(self
+            .run("nix", &["store", "ping", "--store", url])
+            .wait_ok()?)
+    }
+
     fn wait_pinging_store(&self) -> Result<()> {
         while !self.ping_store() {
             thread::sleep(Duration::from_millis(250));
@@ -148,3 +176,21 @@ pub trait NixDriver: LinuxCommandTarget {
 }
 
 impl<T: LinuxCommandTarget> NixDriver for T {}
+
+/// `--offline` in offline mode for use inside scripts, otherwise nothing
+pub fn offline_flag() -> &'static str {
+    if *OFFLINE {
+        "--offline"
+    } else {
+        ""
+    }
+}
+
+/// Refresh cached flake inputs, except in offline mode
+fn refresh_flag() -> &'static str {
+    if *OFFLINE {
+        "--offline"
+    } else {
+        "--refresh"
+    }
+}
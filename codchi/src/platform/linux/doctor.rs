@@ -0,0 +1,239 @@
+use super::{lxd, StoreImpl};
+use crate::{
+    config::DoctorCheck,
+    consts,
+    platform::{CommandExt, NixDriver, PlatformStatus, Store},
+};
+use nix::unistd::{getgid, getgroups, getuid, Group, User};
+use std::{env, fs, path::PathBuf};
+
+const INSTALL_HINT: &str = "See <https://codchi.dev/introduction/installation#linux>.";
+
+/// Linux specific checks of the host setup
+pub fn checks() -> Vec<DoctorCheck> {
+    vec![
+        check_lxd(),
+        check_lxd_group(),
+        check_id_map("/etc/subuid", getuid().as_raw()),
+        check_id_map("/etc/subgid", getgid().as_raw()),
+        check_video_group(),
+        check_gpu(),
+        check_x11(),
+        check_store(),
+        check_daemon_socket(),
+    ]
+}
+
+/// Whether the store container is running and the nix daemon responds
+pub fn store_running() -> bool {
+    matches!(
+        lxd::container::get_platform_status(consts::CONTAINER_STORE_NAME),
+        Ok(PlatformStatus::Running)
+    ) && StoreImpl {}.cmd().ping_store()
+}
+
+fn check_lxd() -> DoctorCheck {
+    const NAME: &str = "LXD";
+    if which::which("lxc").is_err() {
+        return DoctorCheck::fail(
+            NAME,
+            "LXD is not installed (`lxc` not found).",
+            INSTALL_HINT,
+        );
+    }
+    match lxd::lxc_command(&["storage", "list", "--format", "csv"]).output_utf8_ok() {
+        Ok(pools) if pools.trim().is_empty() => DoctorCheck::fail(
+            NAME,
+            "LXD is not initialised (no storage pool found).",
+            "Run `lxd init` (or `sudo lxd init`).",
+        ),
+        Ok(_) => DoctorCheck::pass(NAME, "Installed and initialised"),
+        Err(err) => DoctorCheck::fail(
+            NAME,
+            format!("Failed to access LXD: {err}"),
+            "Make sure the LXD daemon is running and that you are allowed to use it.",
+        ),
+    }
+}
+
+fn check_lxd_group() -> DoctorCheck {
+    const NAME: &str = "Group 'lxd'";
+    if getuid().is_root() {
+        return DoctorCheck::pass(NAME, "Running as root");
+    }
+    match Group::from_name("lxd") {
+        Ok(Some(group)) => {
+            if getgroups().is_ok_and(|groups| groups.contains(&group.gid)) {
+                DoctorCheck::pass(NAME, "Current user is a member")
+            } else if User::from_uid(getuid())
+                .ok()
+                .flatten()
+                .is_some_and(|user| group.mem.contains(&user.name))
+            {
+                DoctorCheck::warn(
+                    NAME,
+                    "Current user was added to the group, but the current session doesn't know \
+about it yet.",
+                    "Log out and in again.",
+                )
+            } else {
+                DoctorCheck::fail(
+                    NAME,
+                    "Current user is not allowed to use LXD.",
+                    "Run `sudo usermod -aG lxd $USER` and log out and in again.",
+                )
+            }
+        }
+        Ok(None) => DoctorCheck::warn(NAME, "Group 'lxd' doesn't exist.", INSTALL_HINT),
+        Err(err) => DoctorCheck::fail(
+            NAME,
+            format!("Failed reading group 'lxd': {err}"),
+            "Check /etc/group.",
+        ),
+    }
+}
+
+/// Codchi maps the current user to root inside containers (`raw.idmap`), which LXD only allows
+/// if root may use the id according to `/etc/subuid` / `/etc/subgid`.
+fn check_id_map(file: &str, id: u32) -> DoctorCheck {
+    let remediation = format!(
+        "Allow LXD to map your user with `echo \"root:{id}:1\" | sudo tee -a {file}` and \
+restart LXD."
+    );
+    let Ok(content) = fs::read_to_string(file) else {
+        return DoctorCheck::warn(file, format!("{file} doesn't exist."), remediation);
+    };
+    let allowed = content.lines().any(|line| {
+        let mut parts = line.trim().split(':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some("root"), Some(start), Some(count)) => {
+                match (start.parse::<u64>(), count.parse::<u64>()) {
+                    (Ok(start), Ok(count)) => (start..start + count).contains(&(id as u64)),
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    });
+    if allowed {
+        DoctorCheck::pass(file, format!("root may map id {id}"))
+    } else {
+        DoctorCheck::fail(file, format!("root may not map id {id}."), remediation)
+    }
+}
+
+fn check_video_group() -> DoctorCheck {
+    const NAME: &str = "Group 'video'";
+    match Group::from_name("video") {
+        Ok(Some(group)) => DoctorCheck::pass(NAME, format!("Exists with gid {}", group.gid)),
+        Ok(None) => DoctorCheck::fail(
+            NAME,
+            "Group 'video' (which is needed for GPU access) not found.",
+            "Create it with `sudo groupadd video`.",
+        ),
+        Err(err) => DoctorCheck::fail(
+            NAME,
+            format!("Failed reading group 'video': {err}"),
+            "Check /etc/group.",
+        ),
+    }
+}
+
+fn check_gpu() -> DoctorCheck {
+    const NAME: &str = "GPU";
+    match fs::read_dir("/dev/dri").map(|devices| devices.count()) {
+        Ok(count) if count > 0 => DoctorCheck::pass(NAME, "/dev/dri is available"),
+        _ => DoctorCheck::warn(
+            NAME,
+            "No GPU found in /dev/dri.",
+            "Graphical applications will fall back to software rendering. Install the drivers \
+of your GPU to enable hardware acceleration or disable GPU passthrough with \
+`codchi passthrough <MACHINE> --gpu off`.",
+        ),
+    }
+}
+
+fn check_x11() -> DoctorCheck {
+    const NAME: &str = "X11";
+    if fs::metadata("/tmp/.X11-unix/X0").is_err() {
+        return DoctorCheck::warn(
+            NAME,
+            "No X11 socket found at /tmp/.X11-unix/X0.",
+            "Graphical applications inside code machines need a X11 server (or XWayland) on \
+display :0.",
+        );
+    }
+    let xauthority = env::var("XAUTHORITY").map(PathBuf::from).ok().or_else(|| {
+        env::var("HOME")
+            .ok()
+            .map(|home| PathBuf::from(home).join(".Xauthority"))
+    });
+    match xauthority {
+        Some(path) if path.exists() => {
+            DoctorCheck::pass(NAME, format!("X11 socket and {path:?} found"))
+        }
+        _ => DoctorCheck::warn(
+            NAME,
+            "No Xauthority file found ($XAUTHORITY or ~/.Xauthority).",
+            "If graphical applications don't start, allow local connections with `xhost \
++local:`. See <https://codchi.dev/introduction/troubleshooting>.",
+        ),
+    }
+}
+
+fn check_store() -> DoctorCheck {
+    const NAME: &str = "Store";
+    match lxd::container::get_platform_status(consts::CONTAINER_STORE_NAME) {
+        Ok(PlatformStatus::Running) => {
+            if (StoreImpl {}).cmd().ping_store() {
+                DoctorCheck::pass(NAME, "Running and the nix daemon responds")
+            } else {
+                DoctorCheck::fail(
+                    NAME,
+                    "The store container is running but the nix daemon doesn't respond.",
+                    format!(
+                        "Restart the store with `lxc restart {}`.",
+                        consts::CONTAINER_STORE_NAME
+                    ),
+                )
+            }
+        }
+        Ok(PlatformStatus::Stopped) => {
+            DoctorCheck::pass(NAME, "Stopped. It is started by the next codchi command.")
+        }
+        Ok(PlatformStatus::NotInstalled) => DoctorCheck::warn(
+            NAME,
+            "The store container is not installed yet.",
+            "It is installed by the next codchi command, e.g. `codchi status`.",
+        ),
+        Err(err) => DoctorCheck::fail(
+            NAME,
+            format!("Failed reading the status of the store container: {err}"),
+            "Fix the problems with LXD reported above.",
+        ),
+    }
+}
+
+/// Code machines access the nix daemon through the socket mounted from the host
+fn check_daemon_socket() -> DoctorCheck {
+    const NAME: &str = "Nix daemon socket";
+    let socket = consts::host::DIR_NIX.join("var/nix/daemon-socket/socket");
+    if socket.exists() {
+        DoctorCheck::pass(NAME, format!("{socket:?} exists"))
+    } else if store_running() {
+        DoctorCheck::fail(
+            NAME,
+            format!("{socket:?} doesn't exist although the store is running."),
+            format!(
+                "Code machines can't access the store. Restart the store with `lxc restart {}`.",
+                consts::CONTAINER_STORE_NAME
+            ),
+        )
+    } else {
+        DoctorCheck::warn(
+            NAME,
+            format!("{socket:?} doesn't exist."),
+            "The socket is created when the store is started.",
+        )
+    }
+}
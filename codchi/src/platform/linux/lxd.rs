@@ -127,8 +127,45 @@ pub mod container {
             listen: String,
             connect: String,
         },
-        #[allow(unused)]
-        Gpu,
+        Gpu {
+            /// Only pass through the GPU with this PCI address
+            pci: Option<String>,
+            /// Only pass through GPUs of this vendor
+            vendor: Option<String>,
+        },
+    }
+
+    pub fn config_device_list(name: &str) -> Result<Vec<String>> {
+        Ok(lxc_command(&["config", "device", "list", name])
+            .output_utf8_ok()?
+            .lines()
+            .map(str::to_string)
+            .collect())
+    }
+
+    pub fn config_device_remove(name: &str, device: &str) -> Result<()> {
+        lxc_command(&["config", "device", "remove", name, device]).wait_ok()?;
+        Ok(())
+    }
+
+    pub fn config_device_get(name: &str, device: &str, key: &str) -> Result<String> {
+        Ok(lxc_command(&["config", "device", "get", name, device, key])
+            .output_utf8_ok()?
+            .trim()
+            .to_string())
+    }
+
+    pub fn config_device_set(name: &str, device: &str, key: &str, value: &str) -> Result<()> {
+        lxc_command(&[
+            "config",
+            "device",
+            "set",
+            name,
+            device,
+            &format!("{key}={value}"),
+        ])
+        .wait_ok()?;
+        Ok(())
     }
 
     pub fn config_mount(container_name: &str, device: &LxdDevice) -> Result<()> {
@@ -157,31 +194,41 @@ to container {container_name}.",
                 name,
                 listen,
                 connect,
-            } => lxc_command(&[
-                "config",
-                "device",
-                "add",
-                container_name,
-                name,
-                "proxy",
-                "bind=instance",
-                &format!("connect={}", connect),
-                &format!("listen={}", listen),
-                &format!("security.uid={}", getuid()),
-                &format!("security.gid={}", getgid()),
-            ])
-            .wait_ok()
-            .with_context(|| {
-                format!(
-                    "Failed to create LXD proxy '{name}' from '{listen}' \
+            } => {
+                let mut cmd = lxc_command(&[
+                    "config",
+                    "device",
+                    "add",
+                    container_name,
+                    name,
+                    "proxy",
+                    "bind=instance",
+                    &format!("connect={}", connect),
+                    &format!("listen={}", listen),
+                    &format!("security.uid={}", getuid()),
+                    &format!("security.gid={}", getgid()),
+                ]);
+                // Sockets in the file system (unlike abstract ones) need to be owned by the
+                // default user
+                if !listen.starts_with("unix:@") {
+                    cmd.args([
+                        format!("uid={}", user::DEFAULT_UID),
+                        format!("gid={}", user::DEFAULT_GID),
+                        "mode=0600".to_string(),
+                    ]);
+                }
+                cmd.wait_ok().with_context(|| {
+                    format!(
+                        "Failed to create LXD proxy '{name}' from '{listen}' \
 to '{connect}' in container {container_name}.",
-                )
-            }),
-            LxdDevice::Gpu => {
+                    )
+                })
+            }
+            LxdDevice::Gpu { pci, vendor } => {
                 let video: Group = Group::from_name("video")?.ok_or(anyhow!(
                     "Group 'video' (which is needed for GPU access) not found."
                 ))?;
-                lxc_command(&[
+                let mut cmd = lxc_command(&[
                     "config",
                     "device",
                     "add",
@@ -189,9 +236,14 @@ to '{connect}' in container {container_name}.",
                     "gpu",
                     "gpu",
                     &format!("gid={}", video.gid),
-                ])
-                .wait_ok()
-                .with_context(|| {
+                ]);
+                if let Some(pci) = pci {
+                    cmd.arg(format!("pci={pci}"));
+                }
+                if let Some(vendor) = vendor {
+                    cmd.arg(format!("vendorid={vendor}"));
+                }
+                cmd.wait_ok().with_context(|| {
                     format!("Failed to create LXD GPU device in container {container_name}.",)
                 })
             }
@@ -1,9 +1,11 @@
+pub mod doctor;
 mod host;
 mod lxd;
 
-use super::{Driver, LinuxCommandTarget, LinuxUser, NixDriver, Store};
+use super::{bash_exec_args, Driver, LinuxCommandTarget, LinuxUser, NixDriver, Store};
 use crate::{
-    cli::DEBUG,
+    cli::{ExecOptions, GpuPassthrough, DEBUG, OFFLINE},
+    config::{CodchiConfig, MachineConfig},
     consts::{self, machine::machine_name, store, user, ToPath},
     logging::{log_progress, set_progress_status, with_suspended_progress},
     platform::{
@@ -14,14 +16,16 @@ use crate::{
 use anyhow::{bail, Context, Result};
 pub use host::*;
 use inquire::Confirm;
+use itertools::Itertools;
 use log::*;
+use nix::{errno::Errno, unistd::Group};
 use std::{
     collections::HashMap,
     env,
     fs::{self, File},
     io::Write,
-    path::PathBuf,
-    process::Command,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
     sync::mpsc::channel,
     thread,
 };
@@ -44,6 +48,20 @@ Please see <https://codchi.dev/introduction/installation#linux> for setup instru
                 consts::CONTAINER_STORE_NAME,
                 &format!("environment.CODCHI_DEBUG={}", if *DEBUG { "1" } else { "" }),
             )?;
+            lxd::container::config_set(
+                consts::CONTAINER_STORE_NAME,
+                &format!(
+                    "environment.CODCHI_OFFLINE={}",
+                    if *OFFLINE { "1" } else { "" }
+                ),
+            )?;
+            // proxies for the nix daemon
+            for (key, value) in CodchiConfig::get().network.proxy_env() {
+                lxd::container::config_set(
+                    consts::CONTAINER_STORE_NAME,
+                    &format!("environment.{key}={value}"),
+                )?;
+            }
             if let Ok(PlatformStatus::Stopped) =
                 lxd::container::get_platform_status(consts::CONTAINER_STORE_NAME)
             {
@@ -75,37 +93,11 @@ Please see <https://codchi.dev/introduction/installation#linux> for setup instru
                 set_progress_status(
                     "Initializing store container. This can take a while the first time...",
                 );
-                let rootfs = env::var("CODCHI_LXD_CONTAINER_STORE")
-                        .map(PathBuf::from)
-                        .context("Failed reading $CODCHI_LXD_CONTAINER_STORE from environment. This indicates a broken build.")?;
-                let mounts = vec![
-                    LxdDevice::Disk {
-                        source: consts::host::DIR_CONFIG.get_or_create()?.clone(),
-                        path: consts::store::DIR_CONFIG.0.clone(),
-                    },
-                    LxdDevice::Disk {
-                        source: consts::host::DIR_DATA.get_or_create()?.clone(),
-                        path: consts::store::DIR_DATA.0.clone(),
-                    },
-                    LxdDevice::Disk {
-                        source: consts::host::DIR_NIX.get_or_create()?.clone(),
-                        path: consts::store::DIR_NIX.0.clone(),
-                    },
-                    // Mount all machine data as gcroots to prevent gc-ing auto roots from e.g. direnv
-                    LxdDevice::Disk {
-                        source: consts::host::DIR_DATA
-                            .get_or_create()?
-                            .join_str(consts::MACHINE_PREFIX)
-                            .clone(),
-                        path: "/nix/var/nix/gcroots/machine-data".to_string(),
-                    },
-                ];
-                lxd::container::install(consts::CONTAINER_STORE_NAME, rootfs, mounts.iter())
-                    .inspect_err(|_err| {
-                        log::error!("Removing leftovers of store files...");
-                        let _ = fs::remove_dir_all(consts::host::DIR_CONFIG.join_store());
-                        let _ = fs::remove_dir_all(consts::host::DIR_DATA.join_store());
-                    })?;
+                install_store_container().inspect_err(|_err| {
+                    log::error!("Removing leftovers of store files...");
+                    let _ = fs::remove_dir_all(consts::host::DIR_CONFIG.join_store());
+                    let _ = fs::remove_dir_all(consts::host::DIR_DATA.join_store());
+                })?;
                 start()
             }
             PlatformStatus::Stopped => start(),
@@ -128,6 +120,36 @@ Please see <https://codchi.dev/introduction/installation#linux> for setup instru
     }
 }
 
+/// Import the store image and attach the host directories
+fn install_store_container() -> Result<()> {
+    let rootfs = env::var("CODCHI_LXD_CONTAINER_STORE")
+        .map(PathBuf::from)
+        .context("Failed reading $CODCHI_LXD_CONTAINER_STORE from environment. This indicates a broken build.")?;
+    let mounts = [
+        LxdDevice::Disk {
+            source: consts::host::DIR_CONFIG.get_or_create()?.clone(),
+            path: consts::store::DIR_CONFIG.0.clone(),
+        },
+        LxdDevice::Disk {
+            source: consts::host::DIR_DATA.get_or_create()?.clone(),
+            path: consts::store::DIR_DATA.0.clone(),
+        },
+        LxdDevice::Disk {
+            source: consts::host::DIR_NIX.get_or_create()?.clone(),
+            path: consts::store::DIR_NIX.0.clone(),
+        },
+        // Mount all machine data as gcroots to prevent gc-ing auto roots from e.g. direnv
+        LxdDevice::Disk {
+            source: consts::host::DIR_DATA
+                .get_or_create()?
+                .join_str(consts::MACHINE_PREFIX)
+                .clone(),
+            path: "/nix/var/nix/gcroots/machine-data".to_string(),
+        },
+    ];
+    lxd::container::install(consts::CONTAINER_STORE_NAME, rootfs, mounts.iter())
+}
+
 pub fn store_debug_shell() -> anyhow::Result<()> {
     LinuxCommandDriver {
         container_name: consts::CONTAINER_STORE_NAME.to_string(),
@@ -137,6 +159,343 @@ pub fn store_debug_shell() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Mount point of a host directory inside the store while moving the data directory
+const STORE_MOVE_MOUNT: &str = "/mnt/codchi-move";
+
+/// Move codchi's data directory (`DIR_DATA`) to `target`, which must be empty or not exist. All
+/// machines and the store are stopped, the data is moved (or copied if `target` is on another file
+/// system) and all LXD disk devices are re-pointed to the new location.
+pub fn store_move(target: &Path) -> Result<()> {
+    let source = consts::host::DIR_DATA.get_or_create()?.clone();
+    let source_canonical = source.canonicalize()?;
+    let target = std::path::absolute(target)?;
+    if target.starts_with(&source_canonical) || source_canonical.starts_with(&target) {
+        bail!("The new data directory {target:?} must not contain or be inside of {source:?}.");
+    }
+    if target.exists() {
+        if fs::read_dir(&target)?.next().is_some() {
+            bail!("The new data directory {target:?} is not empty.");
+        }
+        fs::remove_dir(&target)?;
+    }
+
+    let containers = stop_all_containers()?;
+
+    set_progress_status(format!("Moving {source:?} to {target:?}..."));
+    let copied = match fs::rename(&source, &target) {
+        Ok(()) => false,
+        Err(err) if err.raw_os_error() == Some(Errno::EXDEV as i32) => {
+            if lxd::container::get_platform_status(consts::CONTAINER_STORE_NAME)?
+                == PlatformStatus::NotInstalled
+            {
+                bail!(
+                    "{target:?} is on another file system, so the data has to be copied by the \
+store, which isn't installed. Run `codchi store repair` first."
+                );
+            }
+            fs::create_dir_all(&target)?;
+            // Files inside the machines are owned by subordinate IDs of the host user, so only
+            // root inside a container can copy them with their ownership.
+            StoreImpl::start_or_init_container()?;
+            with_store_mount(&target, |store| {
+                store
+                    .cmd()
+                    .script(format!(
+                        r#"
+set -e
+total="$(find /data | wc -l)"
+cp -a /data/. "{STORE_MOVE_MOUNT}/" &
+pid=$!
+while kill -0 $pid 2>/dev/null; do
+    echo "Copying data... $(find "{STORE_MOVE_MOUNT}" | wc -l) / $total files"
+    sleep 2
+done
+wait $pid
+copied="$(find "{STORE_MOVE_MOUNT}" | wc -l)"
+if [ "$copied" != "$total" ]; then
+    echo "Only $copied of $total files were copied." >&2
+    exit 1
+fi
+"#
+                    ))
+                    .output_ok_streaming(channel().1, set_progress_status)?;
+                Ok(())
+            })
+            .inspect_err(|_| {
+                log::error!("Failed copying the data. Removing leftovers in {target:?}...");
+                clear_dir_in_store(&target)
+                    .and_then(|_| Ok(fs::remove_dir(&target)?))
+                    .trace_err("Failed removing copied data")
+                    .ignore();
+            })?;
+            lxd::container::stop(consts::CONTAINER_STORE_NAME, false)?;
+            true
+        }
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed moving {source:?} to {target:?}"))
+        }
+    };
+
+    set_progress_status("Updating the devices of all containers...");
+    // (container, device, original source) of all changed devices
+    let mut repointed = Vec::new();
+    let updated = (|| {
+        for name in &containers {
+            for device in lxd::container::config_device_list(name)? {
+                let device_source = lxd::container::config_device_get(name, &device, "source")?;
+                let Ok(relative) = Path::new(&device_source)
+                    .strip_prefix(&source)
+                    .or_else(|_| Path::new(&device_source).strip_prefix(&source_canonical))
+                else {
+                    continue;
+                };
+                let new_source = if relative.as_os_str().is_empty() {
+                    target.clone()
+                } else {
+                    target.join(relative)
+                };
+                trace!("Re-pointing device {device} of {name} to {new_source:?}");
+                lxd::container::config_device_set(
+                    name,
+                    &device,
+                    "source",
+                    &new_source.to_string_lossy(),
+                )?;
+                repointed.push((name, device, device_source));
+            }
+        }
+        let mut cfg = CodchiConfig::open_mut()?;
+        cfg.data_dir(&target.to_string_lossy());
+        cfg.write()
+    })();
+    if let Err(err) = updated {
+        log::error!("Failed switching to the new data directory. Restoring {source:?}...");
+        for (name, device, device_source) in repointed {
+            if let Err(err) =
+                lxd::container::config_device_set(name, &device, "source", &device_source)
+            {
+                log::error!(
+                    "Failed restoring the source of device {device} of {name} to \
+{device_source:?}: {err}"
+                );
+            }
+        }
+        let restored = if copied {
+            // the original data is still there, so only the copy is removed
+            StoreImpl::start_or_init_container()
+                .and_then(|_| clear_dir_in_store(&target))
+                .and_then(|_| Ok(fs::remove_dir(&target)?))
+        } else {
+            fs::rename(&target, &source).map_err(anyhow::Error::from)
+        };
+        if let Err(err) = restored {
+            log::error!("Failed restoring {source:?} from {target:?}: {err}");
+        }
+        return Err(err);
+    }
+
+    set_progress_status("Verifying the new data directory...");
+    StoreImpl::start_or_init_container()
+        .context("The store doesn't start with the new data directory")?;
+    for name in &containers {
+        for device in lxd::container::config_device_list(name)? {
+            let device_source = lxd::container::config_device_get(name, &device, "source")?;
+            if Path::new(&device_source).starts_with(&target) {
+                Path::new(&device_source)
+                    .assert_exists()
+                    .with_context(|| format!("Device {device} of {name} is missing."))?;
+            }
+        }
+    }
+
+    if copied {
+        set_progress_status(format!("Deleting old data in {source:?}..."));
+        clear_dir_in_store(&source)?;
+        fs::remove_dir(&source)?;
+    }
+    log::info!("Moved codchi's data to {target:?}.");
+    Ok(())
+}
+
+/// Stop all installed machines and the store. Returns the names of their LXD containers.
+fn stop_all_containers() -> Result<Vec<String>> {
+    let mut containers = MachineConfig::list()?
+        .iter()
+        .map(|cfg| machine_name(&cfg.name))
+        .filter(|name| {
+            !matches!(
+                lxd::container::get_platform_status(name),
+                Ok(PlatformStatus::NotInstalled)
+            )
+        })
+        .collect_vec();
+    containers.push(consts::CONTAINER_STORE_NAME.to_string());
+    // The store is stopped last, because running machines depend on it
+    for name in &containers {
+        if let PlatformStatus::Running = lxd::container::get_platform_status(name)? {
+            set_progress_status(format!("Stopping {name}..."));
+            lxd::container::stop(name, false)?;
+        }
+    }
+    Ok(containers)
+}
+
+/// Recreate the store container from its image and re-attach all devices. `/nix` and all data
+/// are kept.
+pub fn store_repair() -> Result<()> {
+    stop_all_containers()?;
+    if lxd::container::get_platform_status(consts::CONTAINER_STORE_NAME)?
+        != PlatformStatus::NotInstalled
+    {
+        set_progress_status("Deleting the store container...");
+        lxd::container::delete(consts::CONTAINER_STORE_NAME, true)?;
+    }
+    set_progress_status("Importing the store container...");
+    install_store_container()?;
+    <StoreImpl as Store>::init().context("The repaired store doesn't start")?;
+    log::info!(
+        "Repaired `{}`. All machines were stopped and will use the repaired store on their next \
+start.",
+        consts::CONTAINER_STORE_NAME
+    );
+    Ok(())
+}
+
+/// Directories in `/nix/var/nix/gcroots` which survive `codchi store reset`: the roots created
+/// inside machines and their translation for the store (see `register_machine_gcroots`)
+const KEPT_GCROOTS: [&str; 2] = ["auto", "codchi-machines"];
+
+/// Rebuild the store from scratch while keeping `/nix/store` and its database. Afterwards all
+/// machine generations are registered as gcroots again and the store is verified. Lost are the
+/// store's own profiles and all other gcroots, e.g. ones created manually inside the store.
+pub fn store_reset() -> Result<()> {
+    if !with_suspended_progress(|| {
+        Confirm::new("Reset the store?")
+            .with_help_message(
+                "This deletes the store container and its state. Machine files are kept.",
+            )
+            .with_default(false)
+            .prompt()
+    })? {
+        bail!("Canceled reset.");
+    }
+
+    stop_all_containers()?;
+    if lxd::container::get_platform_status(consts::CONTAINER_STORE_NAME)?
+        != PlatformStatus::NotInstalled
+    {
+        set_progress_status("Deleting the store container...");
+        lxd::container::delete(consts::CONTAINER_STORE_NAME, true)?;
+    }
+    set_progress_status("Deleting the store's state...");
+    // Roots of machines (e.g. from `nix build` or direnv) are kept
+    let gcroots = consts::host::DIR_NIX.join("var/nix/gcroots");
+    if gcroots.exists() {
+        for entry in fs::read_dir(&gcroots)? {
+            let entry = entry?;
+            let path = entry.path();
+            if KEPT_GCROOTS.iter().any(|kept| path.ends_with(kept)) {
+                continue;
+            }
+            // links (e.g. `profiles`) point into the store's file system, so don't follow them
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(&path)
+            } else {
+                fs::remove_file(&path)
+            }
+            .with_context(|| format!("Failed deleting {path:?}"))?;
+        }
+    }
+    for dir in [
+        consts::host::DIR_NIX.join("var/nix/profiles"),
+        consts::host::DIR_NIX.join("var/nix/temproots"),
+        consts::host::DIR_CONFIG.join_store(),
+        consts::host::DIR_DATA.join_store(),
+    ] {
+        if dir.exists() {
+            fs::remove_dir_all(&dir).with_context(|| format!("Failed deleting {dir:?}"))?;
+        }
+    }
+
+    set_progress_status("Importing the store container...");
+    install_store_container()?;
+    let store = <StoreImpl as Store>::init().context("The new store doesn't start")?;
+
+    for cfg in MachineConfig::list()? {
+        set_progress_status(format!("Registering the generations of {}...", cfg.name));
+        let missing = store
+            .cmd()
+            .script(format!(
+                r#"
+cd "{}" || exit 0
+for link in system-*-link; do
+    [ -L "$link" ] || continue
+    nix-store --realise "$(readlink "$link")" --add-root "$PWD/$link" >/dev/null 2>&1 \
+        || echo "$link"
+done
+"#,
+                store::DIR_CONFIG.join_machine(&cfg.name)
+            ))
+            .output_utf8_ok()?;
+        if !missing.trim().is_empty() {
+            log::warn!(
+                "Some generations of {name} couldn't be restored ({}). Run `codchi rebuild {name}` \
+if it doesn't start.",
+                missing.lines().join(", "),
+                name = cfg.name
+            );
+        }
+    }
+
+    set_progress_status("Verifying and repairing the store...");
+    store
+        .cmd()
+        .script("nix $NIX_VERBOSITY store verify --all --repair".to_string())
+        .output_ok_streaming(channel().1, |line| {
+            log_progress("store_verify", Level::Info, &line)
+        })
+        .context("Some store paths couldn't be repaired")?;
+
+    log::info!(
+        "Reset `{}`. All machines were stopped and will use the new store on their next start.",
+        consts::CONTAINER_STORE_NAME
+    );
+    Ok(())
+}
+
+/// Mount `dir` from the host at `STORE_MOVE_MOUNT` inside the running store while running `f`
+fn with_store_mount<T>(dir: &Path, f: impl FnOnce(&StoreImpl) -> Result<T>) -> Result<T> {
+    let device = LxdDevice::Disk {
+        source: dir.to_path_buf(),
+        path: STORE_MOVE_MOUNT.to_string(),
+    };
+    lxd::container::config_mount(consts::CONTAINER_STORE_NAME, &device)?;
+    let result = f(&StoreImpl {});
+    lxd::container::config_device_remove(
+        consts::CONTAINER_STORE_NAME,
+        STORE_MOVE_MOUNT
+            .strip_prefix('/')
+            .unwrap_or(STORE_MOVE_MOUNT),
+    )
+    .trace_err("Failed removing temporary mount from store")
+    .ignore();
+    result
+}
+
+/// Delete the contents of `dir` as root inside the store, which is allowed to delete files owned
+/// by the machines' users.
+fn clear_dir_in_store(dir: &Path) -> Result<()> {
+    with_store_mount(dir, |store| {
+        store
+            .cmd()
+            .script(format!(
+                r#"cd "{STORE_MOVE_MOUNT}" && rm -rf -- * .[!.]* ..?*"#
+            ))
+            .wait_ok()?;
+        Ok(())
+    })
+}
+
 impl MachineDriver for Machine {
     fn cmd(&self) -> impl LinuxCommandTarget {
         LinuxCommandDriver {
@@ -153,7 +512,7 @@ impl MachineDriver for Machine {
         let rootfs = env::var("CODCHI_LXD_CONTAINER_MACHINE")
                 .map(PathBuf::from)
                 .context("Failed reading $CODCHI_LXD_CONTAINER_MACHINE from environment. This indicates a broken build.")?;
-        let mounts = vec![
+        let mounts = [
             LxdDevice::Disk {
                 source: consts::host::DIR_NIX.join("store"),
                 path: "/nix/store".to_owned(),
@@ -187,9 +546,9 @@ impl MachineDriver for Machine {
                 listen: "unix:@/tmp/.X11-unix/X0".to_owned(),
                 connect: "unix:@/tmp/.X11-unix/X0".to_owned(),
             },
-            LxdDevice::Gpu,
         ];
         lxd::container::install(&lxd_name, rootfs, mounts.iter())?;
+        self.update_devices()?;
 
         Ok(())
     }
@@ -201,6 +560,8 @@ impl MachineDriver for Machine {
                 name,
                 &format!("environment.CODCHI_DEBUG={}", if *DEBUG { "1" } else { "" }),
             )?;
+            // The host sockets (e.g. `$SSH_AUTH_SOCK`) might have moved since the last start
+            self.update_passthrough_sockets()?;
             lxd::container::start(name)?;
         }
 
@@ -240,6 +601,12 @@ impl MachineDriver for Machine {
                 for (key, value) in env {
                     writeln!(env_file, r#"export CODCHI_{key}="{value}""#)?;
                 }
+                for (key, value) in CodchiConfig::get().network.proxy_env() {
+                    writeln!(env_file, r#"export {key}="{value}""#)?;
+                }
+                for (key, value) in self.passthrough_sockets().into_iter().filter_map(|s| s.env) {
+                    writeln!(env_file, r#"export {key}="{value}""#)?;
+                }
                 env_file.sync_all()?;
                 lxd::container::file_push(
                     &machine_name(&self.config.name),
@@ -286,6 +653,13 @@ tail -f "{log_file}"
             .trace_err("Failed cancelling output stream thread.")
             .ignore();
 
+        self.sync_git_identity()
+            .trace_err("Failed syncing git identity")
+            .ignore();
+        self.sync_gpg_keys()
+            .trace_err("Failed importing public GPG keys")
+            .ignore();
+
         Ok(())
     }
 
@@ -297,44 +671,106 @@ tail -f "{log_file}"
         lxd::container::delete(&machine_name(&self.config.name), true)
     }
 
-    fn create_exec_cmd(&self, cmd: &[&str]) -> super::LinuxCommandBuilder {
-        // let args = [&[consts::user::DEFAULT_NAME], cmd].concat();
-        let cmd = if cmd.is_empty() {
-            self.cmd().raw(
-                "machinectl",
-                &[
-                    &[
-                        "shell",
-                        "-q",
-                        "-E",
-                        "DISPLAY",
-                        "-E",
-                        "XAUTHORITY",
-                        &format!("{}@", consts::user::DEFAULT_NAME),
-                    ],
-                    cmd,
-                ]
-                .concat(),
-            )
-        } else {
-            self.cmd().raw(
-                "machinectl",
-                &[
-                    "shell",
-                    "-q",
-                    "-E",
-                    "DISPLAY",
-                    "-E",
-                    "XAUTHORITY",
-                    &format!("{}@", consts::user::DEFAULT_NAME),
-                    "/bin/bash",
-                    "-c",
-                    &cmd.join(" "),
-                ],
-            )
+    fn create_exec_cmd(&self, cmd: &[&str], options: &ExecOptions) -> super::LinuxCommandBuilder {
+        let mut env = self.passthrough_env();
+        env.extend(options.env());
+        let cwd = options.guest_cwd();
+
+        // `machinectl shell` always allocates a terminal, so run the command directly instead
+        if options.no_tty {
+            let bash_args = bash_exec_args(cmd, options.shell, None);
+            let home = match options.user() {
+                LinuxUser::Root => user::ROOT_HOME.clone(),
+                LinuxUser::Default => user::DEFAULT_HOME.clone(),
+            };
+            return self
+                .cmd()
+                .run("bash", &bash_args.iter().map(String::as_str).collect_vec())
+                .with_cwd(cwd.unwrap_or(home))
+                .with_env(env)
+                .with_user(options.user());
+        }
+
+        let mut args = vec!["shell", "-q", "-E", "DISPLAY", "-E", "XAUTHORITY"];
+        for key in env.keys() {
+            args.extend(["-E", key]);
+        }
+        let user = match options.user() {
+            LinuxUser::Root => "root@".to_string(),
+            LinuxUser::Default => format!("{}@", user::DEFAULT_NAME),
         };
+        args.push(&user);
+
+        // without command and cwd, machinectl opens the login shell of the user
+        let bash_args = bash_exec_args(cmd, options.shell, cwd.as_ref());
+        if !cmd.is_empty() || cwd.is_some() {
+            args.push("/bin/bash");
+            args.extend(bash_args.iter().map(String::as_str));
+        }
 
-        cmd.with_user(LinuxUser::Root)
+        self.cmd()
+            .raw("machinectl", &args)
+            .with_env(env)
+            .with_user(LinuxUser::Root)
+    }
+
+    fn update_devices(&self) -> Result<()> {
+        let lxd_name = machine_name(&self.config.name);
+        for device in lxd::container::config_device_list(&lxd_name)? {
+            if device == "gpu" {
+                lxd::container::config_device_remove(&lxd_name, &device)?;
+            }
+        }
+
+        let gpu = match &self.config.gpu {
+            GpuPassthrough::Off => None,
+            GpuPassthrough::Auto => {
+                let has_dri = fs::read_dir("/dev/dri").is_ok_and(|mut dir| dir.next().is_some());
+                if has_dri && Group::from_name("video").ok().flatten().is_some() {
+                    Some(LxdDevice::Gpu {
+                        pci: None,
+                        vendor: None,
+                    })
+                } else {
+                    log::info!(
+                        "No GPU found on this host. Machine '{}' will use software rendering.",
+                        self.config.name
+                    );
+                    None
+                }
+            }
+            GpuPassthrough::Pci(pci) => {
+                let gpus = list_host_gpus();
+                if !gpus.iter().any(|gpu| &gpu.pci == pci) {
+                    bail!(
+                        "No GPU with PCI address '{pci}' found. Available GPUs: {}",
+                        format_gpus(&gpus)
+                    );
+                }
+                Some(LxdDevice::Gpu {
+                    pci: Some(pci.clone()),
+                    vendor: None,
+                })
+            }
+            GpuPassthrough::Vendor(vendor) => {
+                let gpus = list_host_gpus();
+                if !gpus.iter().any(|gpu| &gpu.vendor == vendor) {
+                    bail!(
+                        "No GPU with vendor id '{vendor}' found. Available GPUs: {}",
+                        format_gpus(&gpus)
+                    );
+                }
+                Some(LxdDevice::Gpu {
+                    pci: None,
+                    vendor: Some(vendor.clone()),
+                })
+            }
+        };
+        if let Some(gpu) = gpu {
+            lxd::container::config_mount(&lxd_name, &gpu)?;
+        }
+
+        self.update_passthrough_sockets()
     }
 
     fn tar(&self, target_file: &std::path::Path) -> Result<()> {
@@ -454,6 +890,16 @@ impl LinuxCommandTarget for LinuxCommandDriver {
         if *DEBUG {
             cmd.args(["--env", "CODCHI_DEBUG=1"]);
         }
+        if *OFFLINE {
+            cmd.args(["--env", "CODCHI_OFFLINE=1"]);
+        }
+        for (key, value) in CodchiConfig::get().network.proxy_env() {
+            cmd.args(["--env", &format!("{key}={value}")]);
+        }
+        // thresholds of ndd's deadlock detection
+        for (key, value) in env::vars().filter(|(key, _)| key.starts_with("NDD_")) {
+            cmd.args(["--env", &format!("{key}={value}")]);
+        }
         if let Some(user) = &user {
             cmd.args([
                 "--user",
@@ -501,3 +947,246 @@ impl LinuxCommandTarget for LinuxCommandDriver {
         arg.to_string()
     }
 }
+
+/// LXD devices of all sockets which can be passed through
+const PASSTHROUGH_DEVICES: [&str; 5] = [
+    "wayland",
+    "pipewire",
+    "pulseaudio",
+    "ssh-agent",
+    "gpg-agent",
+];
+
+/// A unix socket of the host which is passed through to a code machine
+struct PassthroughSocket {
+    name: &'static str,
+    /// Name of the LXD proxy device
+    device: &'static str,
+    /// Path inside the code machine
+    path: &'static str,
+    /// Path relative to `$XDG_RUNTIME_DIR` on the host, or an absolute path
+    host_path: Option<String>,
+    /// Environment variable which is needed to find the socket on the host
+    host_var: &'static str,
+    /// Environment variable (and its value) which points applications to `path`
+    env: Option<(&'static str, String)>,
+}
+
+impl PassthroughSocket {
+    fn host_path(&self) -> Option<PathBuf> {
+        let path = PathBuf::from(self.host_path.as_ref()?);
+        let path = if path.is_absolute() {
+            path
+        } else {
+            PathBuf::from(env::var("XDG_RUNTIME_DIR").ok()?).join(path)
+        };
+        path.exists().then_some(path)
+    }
+}
+
+impl Machine {
+    /// (Re-)create the LXD proxy devices of all enabled sockets
+    fn update_passthrough_sockets(&self) -> Result<()> {
+        let lxd_name = machine_name(&self.config.name);
+        if self.config.gpg_agent {
+            // the extra socket only exists while the agent is running
+            Command::new("gpgconf")
+                .args(["--launch", "gpg-agent"])
+                .wait_ok()
+                .trace_err("Failed launching gpg-agent")
+                .ignore();
+        }
+        for device in lxd::container::config_device_list(&lxd_name)? {
+            if PASSTHROUGH_DEVICES.contains(&device.as_str()) {
+                lxd::container::config_device_remove(&lxd_name, &device)?;
+            }
+        }
+
+        for socket in self.passthrough_sockets() {
+            let Some(connect) = socket.host_path() else {
+                log::warn!(
+                    "Couldn't find the {} socket on this host. Is ${} set?",
+                    socket.name,
+                    socket.host_var
+                );
+                continue;
+            };
+            lxd::container::config_mount(
+                &lxd_name,
+                &LxdDevice::InstanceProxy {
+                    name: socket.device.to_string(),
+                    listen: format!("unix:{}", socket.path),
+                    connect: format!("unix:{}", connect.display()),
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The sockets which are enabled in the machine config. They live in `/tmp`, because it exists
+    /// before LXD starts the proxies (unlike `/run/user/*`).
+    fn passthrough_sockets(&self) -> Vec<PassthroughSocket> {
+        let mut sockets = Vec::new();
+        if self.config.wayland {
+            const PATH: &str = "/tmp/codchi-wayland-0";
+            sockets.push(PassthroughSocket {
+                name: "Wayland",
+                device: "wayland",
+                path: PATH,
+                host_path: Some(env::var("WAYLAND_DISPLAY").unwrap_or("wayland-0".to_string())),
+                host_var: "XDG_RUNTIME_DIR",
+                env: Some(("WAYLAND_DISPLAY", PATH.to_string())),
+            });
+        }
+        if self.config.pipewire {
+            const PATH: &str = "/tmp/codchi-pipewire-0";
+            sockets.push(PassthroughSocket {
+                name: "PipeWire",
+                device: "pipewire",
+                path: PATH,
+                host_path: Some("pipewire-0".to_string()),
+                host_var: "XDG_RUNTIME_DIR",
+                env: Some(("PIPEWIRE_REMOTE", PATH.to_string())),
+            });
+        }
+        if self.config.pulseaudio {
+            const PATH: &str = "/tmp/codchi-pulse-native";
+            sockets.push(PassthroughSocket {
+                name: "PulseAudio",
+                device: "pulseaudio",
+                path: PATH,
+                host_path: Some("pulse/native".to_string()),
+                host_var: "XDG_RUNTIME_DIR",
+                env: Some(("PULSE_SERVER", format!("unix:{PATH}"))),
+            });
+        }
+        if self.config.ssh_agent {
+            const PATH: &str = "/tmp/codchi-ssh-agent";
+            sockets.push(PassthroughSocket {
+                name: "SSH agent",
+                device: "ssh-agent",
+                path: PATH,
+                host_path: env::var("SSH_AUTH_SOCK").ok(),
+                host_var: "SSH_AUTH_SOCK",
+                env: Some(("SSH_AUTH_SOCK", PATH.to_string())),
+            });
+        }
+        if self.config.gpg_agent {
+            // gpg has no variable for the agent's socket. Instead, login shells link it to
+            // `gpgconf --list-dirs agent-socket` (see nix/nixos/driver/lxd).
+            sockets.push(PassthroughSocket {
+                name: "GPG agent",
+                device: "gpg-agent",
+                path: "/tmp/codchi-gpg-agent",
+                host_path: Command::new("gpgconf")
+                    .args(["--list-dirs", "agent-extra-socket"])
+                    .output_utf8_ok()
+                    .map(|path| path.trim().to_string())
+                    .ok()
+                    .or(Some("gnupg/S.gpg-agent.extra".to_string())),
+                host_var: "XDG_RUNTIME_DIR",
+                env: None,
+            });
+        }
+        sockets
+    }
+
+    /// Import the public keyring of the host into the machine, if the gpg-agent is passed through.
+    /// Skipped if the keyring didn't change since the last import.
+    fn sync_gpg_keys(&self) -> Result<()> {
+        if !self.config.gpg_agent {
+            return Ok(());
+        }
+        let keys = Command::new("gpg")
+            .args(["--export", "--armor"])
+            .output_utf8_ok()
+            .context("Failed exporting the public keys of the host.")?;
+        if keys.trim().is_empty() {
+            return Ok(());
+        }
+
+        const SCRIPT: &str = r#"
+command -v gpg >/dev/null || exit 0
+keys="$(cat)"
+stamp=~/.gnupg/.codchi-pubring
+hash="$(printf '%s' "$keys" | sha256sum)"
+[ -f "$stamp" ] && [ "$(cat "$stamp")" = "$hash" ] && exit 0
+mkdir -p -m 700 ~/.gnupg
+printf '%s\n' "$keys" | gpg --batch --quiet --import
+echo "$hash" > "$stamp"
+"#;
+        let mut cmd: Command = self
+            .cmd()
+            .run("bash", &["-c", SCRIPT, "codchi-gpg"])
+            .with_user(LinuxUser::Default)
+            .into();
+        let mut child = cmd.stdin(Stdio::piped()).stdout(Stdio::null()).spawn()?;
+        child
+            .stdin
+            .take()
+            .context("Missing stdin.")?
+            .write_all(keys.as_bytes())?;
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("Importing the public keys failed with {status}.");
+        }
+        Ok(())
+    }
+
+    /// Environment variables which point applications inside the machine to the passed through
+    /// sockets
+    fn passthrough_env(&self) -> HashMap<String, String> {
+        let mut env: HashMap<String, String> = self
+            .passthrough_sockets()
+            .into_iter()
+            .filter_map(|socket| socket.env)
+            .map(|(key, value)| (key.to_string(), value))
+            .collect();
+        if !env.is_empty() {
+            env.insert(
+                "XDG_RUNTIME_DIR".to_string(),
+                format!("/run/user/{}", consts::user::DEFAULT_UID),
+            );
+        }
+        env
+    }
+}
+
+/// A GPU of the host as listed in `/sys/class/drm`
+struct HostGpu {
+    pci: String,
+    vendor: String,
+}
+
+fn list_host_gpus() -> Vec<HostGpu> {
+    let Ok(entries) = fs::read_dir("/sys/class/drm") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            // skip connectors like card0-HDMI-A-1
+            name.starts_with("card") && !name.contains('-')
+        })
+        .filter_map(|entry| {
+            let device = fs::canonicalize(entry.path().join("device")).ok()?;
+            let pci = device.file_name()?.to_string_lossy().to_string();
+            let vendor = fs::read_to_string(device.join("vendor")).ok()?;
+            Some(HostGpu {
+                pci,
+                vendor: vendor.trim().trim_start_matches("0x").to_string(),
+            })
+        })
+        .collect()
+}
+
+fn format_gpus(gpus: &[HostGpu]) -> String {
+    if gpus.is_empty() {
+        "none".to_string()
+    } else {
+        gpus.iter()
+            .map(|gpu| format!("pci={} (vendor={})", gpu.pci, gpu.vendor))
+            .join(", ")
+    }
+}
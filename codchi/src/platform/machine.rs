@@ -1,13 +1,19 @@
 use super::{
-    platform::HostImpl, Host, LinuxCommandBuilder, LinuxCommandTarget, LinuxUser, NixDriver,
+    nix, platform::HostImpl, Host, LinuxCommandBuilder, LinuxCommandTarget, LinuxUser, NixDriver,
 };
 use crate::{
-    cli::CODCHI_DRIVER_MODULE, config::{EnvSecret, FlakeLocation, MachineConfig}, consts::{self, host, ToPath}, logging::{hide_progress, log_progress, set_progress_status, with_suspended_progress}, platform::{self, CommandExt, Driver, Store}, progress_scope, util::PathExt
+    cli::{ExecOptions, CODCHI_DRIVER_MODULE, OFFLINE}, config::{EnvSecret, FlakeLocation, MachineConfig}, consts::{self, host, ToPath}, logging::{hide_progress, log_progress, record_build_log, set_progress_status, with_suspended_progress}, platform::{self, CommandExt, Driver, Store}, progress_scope, ssh, util::{LinuxPath, PathExt, ResultExt, UtilExt}
 };
 use anyhow::{bail, Context, Result};
 use itertools::Itertools;
 use log::Level;
-use std::{collections::HashMap, fs, sync::mpsc::channel, thread};
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::Path,
+    sync::mpsc::channel,
+    thread,
+};
 
 pub trait MachineDriver: Sized {
     fn cmd(&self) -> impl LinuxCommandTarget;
@@ -27,7 +33,13 @@ pub trait MachineDriver: Sized {
     /// Delete container
     fn delete_container(&self) -> Result<()>;
 
-    fn create_exec_cmd(&self, cmd: &[&str]) -> LinuxCommandBuilder;
+    /// Run `cmd` or open a login shell if `cmd` is empty. With `options.shell`, `cmd` is
+    /// interpreted by bash. Otherwise its arguments are passed as-is.
+    fn create_exec_cmd(&self, cmd: &[&str], options: &ExecOptions) -> LinuxCommandBuilder;
+
+    /// Add or remove passthrough devices (like the GPU) according to the machine config. Works
+    /// on installed machines without reinstalling them.
+    fn update_devices(&self) -> Result<()>;
 
     /// Export file system of a machine to a tar WITHOUT starting the store or the machine.
     fn tar(&self, target_file: &std::path::Path) -> Result<()>;
@@ -197,6 +209,13 @@ fi
     }
 
     pub fn update_flake(&self) -> Result<()> {
+        if *OFFLINE {
+            log::warn!(
+                "Not fetching module updates for {} in offline mode.",
+                self.config.name
+            );
+            return Ok(());
+        }
         Driver::store()
             .cmd()
             .script(r#"ndd $NIX_VERBOSITY flake update"#.to_string())
@@ -207,6 +226,30 @@ fi
         Ok(())
     }
 
+    /// Only update the `codchi_driver` input of the machine's flake.lock
+    pub fn update_driver(&self) -> Result<()> {
+        Driver::store()
+            .cmd()
+            .script(format!(
+                r#"ndd $NIX_VERBOSITY {offline} flake lock --update-input {CODCHI_DRIVER_MODULE}"#,
+                offline = nix::offline_flag(),
+            ))
+            .with_cwd(consts::store::DIR_CONFIG.join_machine(&self.config.name))
+            .output_ok_streaming(channel().1, |line| {
+                log_progress("build", log::Level::Debug, &line)
+            })?;
+        Ok(())
+    }
+
+    /// Point the machine to the driver of the installed codchi and rebuild it without updating
+    /// other modules.
+    pub fn upgrade(&self) -> Result<()> {
+        self.write_flake()?;
+        set_progress_status(format!("Upgrading driver of {}...", self.config.name));
+        self.update_driver()?;
+        self.build(true)
+    }
+
     pub fn build(&self, no_update: bool) -> Result<()> {
         self.write_flake()?;
 
@@ -238,31 +281,41 @@ fi
         };
 
         set_progress_status(format!("Building {}...", self.config.name));
+        let build_log = record_build_log(&host::machine_build_logs(&self.config.name))?;
         if !no_update {
             self.update_flake()?;
         }
         Driver::store()
             .cmd()
-            .script(
+            .script(format!(
                 r#"
-NIX_CFG_FILE="$(ndd build $NIX_VERBOSITY --no-link --print-out-paths \
+NIX_CFG_FILE="$(ndd build $NIX_VERBOSITY {offline} --no-link --print-out-paths \
     '.#nixosConfigurations.default.config.environment.etc."nix/nix.conf".source')"
 export NIX_CONFIG="$(cat $NIX_CFG_FILE)"
 if [ ! -e system ]; then
-  ndd $NIX_VERBOSITY profile install --option warn-dirty false --profile system \
+  ndd $NIX_VERBOSITY {offline} profile install --option warn-dirty false --profile system \
         '.#nixosConfigurations.default.config.system.build.toplevel'
 else
-  ndd $NIX_VERBOSITY profile upgrade --option warn-dirty false --profile system '.*'
+  ndd $NIX_VERBOSITY {offline} profile upgrade --option warn-dirty false --profile system '.*'
 fi
 pwd
 git add flake.*
-"#
-                .to_string(),
-            )
+"#,
+                offline = nix::offline_flag(),
+            ))
             .with_cwd(consts::store::DIR_CONFIG.join_machine(&self.config.name))
             .output_ok_streaming(channel().1, |line| {
                 log_progress("build", log::Level::Debug, &line)
+            })
+            .with_context(|| {
+                format!(
+                    "Failed building {name}. The full log was saved to {path:?}. \
+Show it with `codchi logs {name}`.",
+                    name = self.config.name,
+                    path = build_log.path(),
+                )
             })?;
+        drop(build_log);
 
         if awaker.is_some() {
             log::trace!(
@@ -344,6 +397,16 @@ git add flake.*
         HostImpl::write_machine_shortcuts(self)?;
         HostImpl::post_install(&self.config.name)?;
 
+        let (lock, mut cfg) = MachineConfig::open_existing(&self.config.name, true)?;
+        cfg.driver_url = Some(consts::CODCHI_FLAKE_URL.to_string());
+        cfg.write(lock)?;
+
+        // Every build adds a generation, so the retention policy is applied after each one
+        Driver::store()
+            .gc_policy(false, false)
+            .trace_err("Failed applying the garbage collection policy")
+            .ignore();
+
         hide_progress();
 
         Ok(())
@@ -394,6 +457,11 @@ git add flake.*
             self.config.name,
             fs::remove_dir_all(host::DIR_DATA.join_machine(&self.config.name))
         );
+        log::trace!(
+            "Deleting ssh keys for {}: {:?}",
+            self.config.name,
+            fs::remove_dir_all(ssh::keys_dir(&self.config.name))
+        );
 
         set_progress_status("Deleting start menu shortcuts...");
         HostImpl::delete_shortcuts(&self.config.name)?;
@@ -404,7 +472,7 @@ git add flake.*
         Ok(())
     }
 
-    pub fn exec(&self, cmd: &[String]) -> Result<()> {
+    pub fn exec(&self, cmd: &[String], options: &ExecOptions) -> Result<()> {
         if self.config_status == ConfigStatus::NotInstalled
             || self.platform_status == PlatformStatus::NotInstalled
         {
@@ -418,14 +486,99 @@ git add flake.*
         #[cfg(target_os = "windows")]
         Driver::host().start_vcxsrv(false)?;
 
+        let mut options = options.clone();
+        if options.cwd.is_none() {
+            options.cwd = env::current_dir()
+                .ok()
+                .and_then(|cwd| self.host_path_to_guest(&cwd))
+                .map(|cwd| cwd.0);
+        }
+        let cmd = if options.map_paths {
+            cmd.iter()
+                .map(|arg| {
+                    let path = Path::new(arg);
+                    if path.is_relative() && !path.exists() {
+                        return arg.clone();
+                    }
+                    self.host_path_to_guest(path)
+                        .map(|path| path.0)
+                        .unwrap_or(arg.clone())
+                })
+                .collect_vec()
+        } else {
+            cmd.to_vec()
+        };
+
         set_progress_status(format!("Starting {}...", self.config.name));
         self.start()?;
         hide_progress();
-        self.create_exec_cmd(&cmd.iter().map(|str| str.as_str()).collect_vec())
+        self.create_exec_cmd(&cmd.iter().map(|str| str.as_str()).collect_vec(), &options)
             .exec()?;
         Ok(())
     }
 
+    /// Translate a host path inside the home of this machine (`DIR_DATA/machine/<name>`) into the
+    /// corresponding path inside the machine. The path doesn't need to exist, but its parent does.
+    pub fn host_path_to_guest(&self, path: &Path) -> Option<LinuxPath> {
+        let home = host::DIR_DATA
+            .join_machine(&self.config.name)
+            .canonicalize()
+            .ok()?;
+        let path = path.canonicalize().ok().or_else(|| {
+            let parent = std::path::absolute(path).ok()?.parent()?.canonicalize().ok()?;
+            Some(parent.join(path.file_name()?))
+        })?;
+        let relative = path.strip_prefix(&home).ok()?;
+        Some(
+            relative
+                .components()
+                .fold(consts::user::DEFAULT_HOME.clone(), |guest, component| {
+                    guest.join_str(&component.as_os_str().to_string_lossy())
+                }),
+        )
+    }
+
+    /// Copy `user.name` and `user.email` of the host's global git config into `~/.gitconfig` of
+    /// the machine, if enabled. The machine must be running.
+    pub fn sync_git_identity(&self) -> Result<()> {
+        if !self.config.git_identity {
+            return Ok(());
+        }
+        let host_config = |key: &str| {
+            std::process::Command::new("git")
+                .args(["config", "--global", "--get", key])
+                .output_utf8_ok()
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        let (Some(name), Some(email)) = (host_config("user.name"), host_config("user.email"))
+        else {
+            log::warn!(
+                "Couldn't read `user.name` and `user.email` from the git config of this host. \
+Skipping git identity of machine '{}'.",
+                self.config.name
+            );
+            return Ok(());
+        };
+
+        // `~/.config/git/config` might be managed (read only) by the machine's configuration
+        const SCRIPT: &str = r#"
+command -v git >/dev/null || exit 0
+for kv in "user.name=$1" "user.email=$2"; do
+    key="${kv%%=*}"; value="${kv#*=}"
+    if [ "$(git config --file ~/.gitconfig --get "$key")" != "$value" ]; then
+        git config --file ~/.gitconfig "$key" "$value"
+    fi
+done
+"#;
+        self.cmd()
+            .run("bash", &["-c", SCRIPT, "codchi-git", &name, &email])
+            .with_user(LinuxUser::Default)
+            .wait_ok()
+            .context("Failed copying the git identity into the machine.")
+    }
+
     pub fn run_init_script(&self) -> Result<()> {
         progress_scope! {
             set_progress_status(format!("Running init script of machine '{}'...", self.config.name));
@@ -435,3 +588,32 @@ git add flake.*
         Ok(())
     }
 }
+
+impl ExecOptions {
+    pub fn user(&self) -> LinuxUser {
+        if self.root {
+            LinuxUser::Root
+        } else {
+            LinuxUser::Default
+        }
+    }
+
+    /// The working directory inside the machine. Relative paths are relative to the home of the
+    /// default user.
+    pub fn guest_cwd(&self) -> Option<LinuxPath> {
+        self.cwd.as_ref().map(|cwd| {
+            if cwd.starts_with('/') {
+                LinuxPath(cwd.clone())
+            } else {
+                consts::user::DEFAULT_HOME.join_str(cwd)
+            }
+        })
+    }
+
+    pub fn env(&self) -> HashMap<String, String> {
+        self.env
+            .iter()
+            .map(|var| (var.key.clone(), var.value.clone()))
+            .collect()
+    }
+}
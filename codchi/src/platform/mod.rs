@@ -19,11 +19,15 @@ use anyhow::Result;
 use platform::HostImpl;
 use std::sync::OnceLock;
 
+pub use platform::doctor;
 pub use platform::store_debug_shell;
 
 #[cfg(target_os = "windows")]
 pub use platform::store_recover;
 
+#[cfg(target_os = "linux")]
+pub use platform::{store_move, store_repair, store_reset};
+
 pub struct Driver {
     store: StoreImpl,
 }
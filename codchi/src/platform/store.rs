@@ -1,22 +1,136 @@
 use super::{platform, CommandExt, LinuxCommandTarget, NixDriver};
 use crate::{
-    config::MachineConfig,
+    config::{
+        CodchiConfig, GcConfig, GcGenerations, GcOutput, MachineConfig, StoreNixConfig, StoreUsage,
+        StoreUsageEntry, StoreVerifyOutput,
+    },
     consts::{self, store, ToPath},
-    util::{LinuxPath, PathExt},
+    util::{human_size, LinuxPath, PathExt},
 };
 use crate::{
-    logging::{log_progress, set_progress_status},
+    logging::{log_progress, record_build_log, set_progress_status},
     progress_scope,
 };
 use anyhow::{bail, Context, Result};
+use lazy_regex::regex_captures;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs::{self, File},
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::mpsc::channel,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
+/// Valid store paths with their NAR size and references, as reported by `nix path-info --all`
+pub struct StorePaths(HashMap<String, (u64, Vec<String>)>);
+
+impl StorePaths {
+    pub fn read(store: &impl Store) -> Result<Self> {
+        let json: serde_json::Value = store
+            .cmd()
+            .run("nix", &["path-info", "--all", "--json"])
+            .output_json()?;
+        let info = |path: &str, info: &serde_json::Value| {
+            let size = info["narSize"].as_u64().unwrap_or_default();
+            let references = info["references"]
+                .as_array()
+                .map(|refs| {
+                    refs.iter()
+                        .filter_map(|r| r.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default();
+            (path.to_string(), (size, references))
+        };
+        // nix >= 2.19 returns an object keyed by path, older versions a list
+        let paths = match &json {
+            serde_json::Value::Object(paths) => paths
+                .iter()
+                .map(|(path, value)| info(path, value))
+                .collect(),
+            serde_json::Value::Array(paths) => paths
+                .iter()
+                .filter_map(|value| Some(info(value["path"].as_str()?, value)))
+                .collect(),
+            _ => bail!("Unexpected output of `nix path-info`: {json}"),
+        };
+        Ok(Self(paths))
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+
+    /// All paths which are reachable from `roots`
+    pub fn closure<'a>(&'a self, roots: impl IntoIterator<Item = &'a str>) -> HashSet<&'a str> {
+        let mut closure = HashSet::new();
+        let mut todo: Vec<&str> = roots.into_iter().collect();
+        while let Some(path) = todo.pop() {
+            let Some((path, (_, references))) = self.0.get_key_value(path) else {
+                continue;
+            };
+            if closure.insert(path.as_str()) {
+                todo.extend(references.iter().map(String::as_str));
+            }
+        }
+        closure
+    }
+
+    pub fn size<'a>(&self, paths: impl Iterator<Item = &'a str>) -> u64 {
+        paths
+            .filter_map(|path| self.0.get(path))
+            .map(|(size, _)| size)
+            .sum()
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.0.values().map(|(size, _)| size).sum()
+    }
+}
+
+fn unix_now() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// Select the generations to delete. The current generation is never deleted.
+/// * `generations` - `(number, creation time, is current)`, sorted by number
+/// * `min_age` - Like `nix profile wipe-history --older-than`: Keep the newest generation created
+///   before `now - min_age` days, because it was still active back then. Delete all older ones.
+///   `None` (or 0 days) selects all generations.
+/// * `keep` - Always keep the newest `keep` generations
+fn wiped_generations(
+    generations: &[(u32, u64, bool)],
+    min_age: Option<u16>,
+    keep: u32,
+    now: u64,
+) -> Vec<u32> {
+    let old = match min_age {
+        None | Some(0) => generations,
+        Some(days) => {
+            let cutoff = now.saturating_sub(u64::from(days) * 24 * 60 * 60);
+            match generations
+                .iter()
+                .rposition(|(_, created, _)| *created < cutoff)
+            {
+                Some(keep) => &generations[..keep],
+                None => &[],
+            }
+        }
+    };
+    let kept_from = generations.len().saturating_sub(keep as usize);
+    old.iter()
+        .take(kept_from)
+        .filter(|(_, _, current)| !current)
+        .map(|(gen, _, _)| *gen)
+        .collect()
+}
+
+/// Prefix of the line with the exit status of `nix store verify`
+const VERIFY_STATUS: &str = "codchi-verify-status: ";
+
+/// Directory inside the store where the auto roots of all machines are registered
+pub const MACHINE_GCROOTS: &str = "/nix/var/nix/gcroots/codchi-machines";
 
 /// Internal name of driver module in codchi's NixOS modules
 pub const NIXOS_DRIVER_NAME: &str = platform::NIXOS_DRIVER_NAME;
@@ -52,6 +166,8 @@ pub trait Store: Sized {
             file.write_all(flake_content.as_bytes())?;
             file.sync_all()?;
         }
+        write_nix_conf().context("Failed writing nix settings of the store")?;
+        write_ca_certificates().context("Failed writing CA certificates of the store")?;
 
         progress_scope! {
             set_progress_status("Starting store container...");
@@ -62,26 +178,17 @@ pub trait Store: Sized {
     /// Get driver for running commands inside store
     fn cmd(&self) -> impl NixDriver;
 
-    fn gc(&self, min_age: Option<u16>, all: bool, machine_names: &Vec<String>) -> Result<()> {
-        #[cfg(target_family = "windows")]
-        {
-            if !inquire::Confirm::new(
-                "Currently, garbage collection will delete user-created roots for example when \
-using 'nix build' or direnv. Still procceed?",
-            )
-            .prompt()?
-            {
-                bail!("Operation was canceled by the user");
-            }
-        }
-        set_progress_status("Deleting dead store paths...");
+    /// Delete old generations (if `min_age` is set) and collect garbage. With `dry_run`, only
+    /// calculate what would be deleted.
+    fn gc(
+        &self,
+        min_age: Option<u16>,
+        all: bool,
+        machine_names: &Vec<String>,
+        dry_run: bool,
+    ) -> Result<GcOutput> {
+        let mut generations = Vec::new();
         if let Some(min_age) = min_age {
-            let mut args = vec!["profile", "wipe-history", "--profile", "system"];
-            let min_age_str = format!("{}d", min_age);
-            if min_age > 0 {
-                args.extend(["--older-than", &min_age_str]);
-            }
-
             let machines = if all {
                 MachineConfig::list()?
             } else {
@@ -98,21 +205,467 @@ using 'nix build' or direnv. Still procceed?",
                 }
                 machines
             };
+            let now = unix_now()?;
             for machine in machines {
-                self.cmd()
-                    .run("nix", &args)
-                    .with_cwd(store::DIR_CONFIG.join_machine(&machine.name))
-                    .wait_ok()?;
+                generations.push(GcGenerations {
+                    generations: wiped_generations(
+                        &self.list_generations(&machine.name)?,
+                        Some(min_age),
+                        0,
+                        now,
+                    ),
+                    machine: machine.name,
+                });
+            }
+        }
+        self.collect_garbage(generations, dry_run)
+    }
+
+    /// Apply the retention policy of codchi's config (`[gc]`) to all machines. Unless `force`d,
+    /// this only happens if the store exceeds `max_store_size` or the free space falls below
+    /// `min_free_space` (if any of them is set). Each run is appended to `DIR_DATA/log/gc.log`.
+    fn gc_policy(&self, force: bool, dry_run: bool) -> Result<Option<GcOutput>> {
+        let policy = &CodchiConfig::get().gc;
+        if !policy.is_enabled() {
+            return Ok(None);
+        }
+        if !force {
+            if let Some(reason) = self.gc_threshold_reason(policy)? {
+                log::info!("Running automatic garbage collection: {reason}");
+            } else if policy.max_store_size.is_some() || policy.min_free_space.is_some() {
+                log::debug!("Skipping automatic garbage collection, no threshold was exceeded.");
+                return Ok(None);
+            }
+        }
+
+        let now = unix_now()?;
+        let mut generations = Vec::new();
+        for machine in MachineConfig::list()? {
+            generations.push(GcGenerations {
+                generations: wiped_generations(
+                    &self.list_generations(&machine.name)?,
+                    policy.delete_older_than,
+                    policy.keep_generations.unwrap_or(0),
+                    now,
+                ),
+                machine: machine.name,
+            });
+        }
+        let output = self.collect_garbage(generations, dry_run)?;
+
+        if !dry_run {
+            (|| {
+                if let Some(dir) = consts::host::LOGFILE_GC.parent() {
+                    dir.get_or_create()?;
+                }
+                let mut log = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&*consts::host::LOGFILE_GC)?;
+                let mut entry = serde_json::to_value(&output)?;
+                entry["time"] = now.into();
+                entry["forced"] = force.into();
+                writeln!(log, "{entry}")?;
+                anyhow::Ok(())
+            })()
+            .context("Failed writing the garbage collection log")?;
+        }
+        Ok(Some(output))
+    }
+
+    /// Why the store needs an automatic garbage collection, if any
+    fn gc_threshold_reason(&self, policy: &GcConfig) -> Result<Option<String>> {
+        // binary units like `human_size`
+        const GIB: u64 = 1024 * 1024 * 1024;
+        if let Some(max) = policy.max_store_size {
+            let size = StorePaths::read(self)?.total_size();
+            if size > max.saturating_mul(GIB) {
+                return Ok(Some(format!(
+                    "The store uses {} (max_store_size = {max} GiB).",
+                    human_size(size)
+                )));
+            }
+        }
+        if let Some(min) = policy.min_free_space {
+            let available = self
+                .cmd()
+                .run("df", &["-Pk", "/nix/store"])
+                .output_utf8_ok()?
+                .lines()
+                .nth(1)
+                .and_then(|line| line.split_whitespace().nth(3)?.parse::<u64>().ok())
+                .context("Failed reading the free disk space of the store.")?
+                .saturating_mul(1024);
+            if available < min.saturating_mul(GIB) {
+                return Ok(Some(format!(
+                    "Only {} of disk space are left (min_free_space = {min} GiB).",
+                    human_size(available)
+                )));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Delete the given generations and all store paths which aren't referenced anymore. With
+    /// `dry_run`, only calculate what would be deleted.
+    fn collect_garbage(&self, generations: Vec<GcGenerations>, dry_run: bool) -> Result<GcOutput> {
+        // Also in a dry run, because otherwise roots which weren't registered yet would count as
+        // garbage. Registering only adds roots of existing paths and drops dangling ones, so no
+        // store path is affected.
+        self.register_machine_gcroots()
+            .context("Failed registering the gcroots of the code machines")?;
+        set_progress_status("Calculating store usage...");
+        let before = StorePaths::read(self)?;
+
+        if dry_run {
+            let wiped: HashSet<String> = generations
+                .iter()
+                .flat_map(|gens| {
+                    gens.generations.iter().map(|gen| {
+                        store::DIR_CONFIG
+                            .join_machine(&gens.machine)
+                            .join_str(&format!("system-{gen}-link"))
+                            .0
+                    })
+                })
+                .collect();
+            let roots = self.gc_roots()?;
+            let live = before.closure(
+                roots
+                    .iter()
+                    .filter(|(link, _)| !wiped.contains(link))
+                    .map(|(_, path)| path.as_str()),
+            );
+            let dead = before
+                .paths()
+                .filter(|path| !live.contains(path))
+                .collect::<Vec<_>>();
+            let freed_bytes = before.size(dead.iter().copied());
+            return Ok(GcOutput {
+                dry_run,
+                generations,
+                deleted_paths: dead.len(),
+                freed_bytes,
+                store_size: before.total_size() - freed_bytes,
+            });
+        }
+
+        set_progress_status("Deleting dead store paths...");
+        let _build_log = record_build_log(&consts::host::DIR_STORE_BUILD_LOGS)?;
+        for gens in &generations {
+            if gens.generations.is_empty() {
+                continue;
             }
+            let numbers = gens
+                .generations
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>();
+            let mut args = vec!["--profile", "system", "--delete-generations"];
+            args.extend(numbers.iter().map(String::as_str));
+            self.cmd()
+                .run("nix-env", &args)
+                .with_cwd(store::DIR_CONFIG.join_machine(&gens.machine))
+                .wait_ok()?;
         }
         self.cmd()
             .script("nix $NIX_VERBOSITY store gc".to_string())
             .output_ok_streaming(channel().1, |line| {
                 log_progress("gc", log::Level::Debug, &line)
             })?;
+
+        set_progress_status("Calculating store usage...");
+        let after = StorePaths::read(self)?;
+        Ok(GcOutput {
+            dry_run,
+            generations,
+            deleted_paths: before.paths().count().saturating_sub(after.paths().count()),
+            freed_bytes: before.total_size().saturating_sub(after.total_size()),
+            store_size: after.total_size(),
+        })
+    }
+
+    /// All generations of a machine's system profile as `(number, creation time, is current)`,
+    /// sorted by number
+    fn list_generations(&self, machine_name: &str) -> Result<Vec<(u32, u64, bool)>> {
+        let output = self
+            .cmd()
+            .script(format!(
+                r#"
+cd "{}" || exit 0
+current="$(readlink system || true)"
+for link in system-*-link; do
+    [ -L "$link" ] || continue
+    n="${{link#system-}}"
+    echo "${{n%-link}} $(stat -c %Y "$link") $([ "$link" = "$current" ] && echo current)"
+done
+"#,
+                store::DIR_CONFIG.join_machine(machine_name)
+            ))
+            .output_utf8_ok()?;
+        let mut generations = output
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                Some((
+                    fields.next()?.parse::<u32>().ok()?,
+                    fields.next()?.parse::<u64>().ok()?,
+                    fields.next() == Some("current"),
+                ))
+            })
+            .collect::<Vec<_>>();
+        generations.sort();
+        Ok(generations)
+    }
+
+    /// All gcroots of the store as `(link, store path)`
+    fn gc_roots(&self) -> Result<Vec<(String, String)>> {
+        Ok(self
+            .cmd()
+            .run("nix-store", &["--gc", "--print-roots"])
+            .output_utf8_ok()?
+            .lines()
+            .filter_map(|line| {
+                let (link, path) = line.rsplit_once(" -> ")?;
+                Some((link.to_string(), path.to_string()))
+            })
+            .collect())
+    }
+
+    /// Break down the store usage by machine
+    fn usage(&self) -> Result<StoreUsage> {
+        self.register_machine_gcroots()
+            .context("Failed registering the gcroots of the code machines")?;
+        set_progress_status("Calculating store usage...");
+        let paths = StorePaths::read(self)?;
+        let roots = self.gc_roots()?;
+        let machine_names = MachineConfig::list()?
+            .into_iter()
+            .map(|cfg| cfg.name)
+            .collect::<Vec<_>>();
+
+        // roots are attributed to a machine by their location, e.g. `/config/machine/<name>/...`
+        let machine_of = |link: &str| {
+            machine_names.iter().find(|name| {
+                [
+                    format!("/{}/{name}/", consts::MACHINE_PREFIX),
+                    format!("{MACHINE_GCROOTS}/{name}/"),
+                ]
+                .iter()
+                .any(|prefix| link.contains(prefix.as_str()))
+            })
+        };
+        let mut machine_roots: HashMap<&String, Vec<&str>> = HashMap::new();
+        let mut other_roots = Vec::new();
+        for (link, path) in &roots {
+            match machine_of(link) {
+                Some(name) => machine_roots.entry(name).or_default().push(path),
+                None => other_roots.push(path.as_str()),
+            }
+        }
+
+        // paths which are referenced by more than one machine or by the store itself are shared
+        let mut owners: HashMap<&str, usize> = HashMap::new();
+        let other = paths.closure(other_roots);
+        let closures = machine_names
+            .iter()
+            .map(|name| {
+                let closure = paths.closure(machine_roots.remove(name).unwrap_or_default());
+                for path in &closure {
+                    *owners.entry(path).or_default() += 1;
+                }
+                (name, closure)
+            })
+            .collect::<Vec<_>>();
+        let exclusive = |path: &&str| owners.get(path) == Some(&1) && !other.contains(path);
+
+        let entry = |name: &str, paths_iter: Vec<&str>| StoreUsageEntry {
+            name: name.to_string(),
+            paths: paths_iter.len(),
+            bytes: paths.size(paths_iter.into_iter()),
+        };
+        let machines = closures
+            .iter()
+            .map(|(name, closure)| entry(name, closure.iter().copied().filter(exclusive).collect()))
+            .collect();
+        let shared = paths
+            .paths()
+            .filter(|path| (owners.contains_key(path) || other.contains(path)) && !exclusive(path))
+            .collect();
+        let garbage = paths
+            .paths()
+            .filter(|path| !owners.contains_key(path) && !other.contains(path))
+            .collect();
+        Ok(StoreUsage {
+            machines,
+            shared: entry("shared", shared),
+            garbage: entry("garbage", garbage),
+            total: entry("total", paths.paths().collect()),
+        })
+    }
+
+    /// Check the contents of all store paths (or only the closure of a machine's current system)
+    /// against their hashes with `nix store verify`. With `repair`, corrupted paths are
+    /// substituted or rebuilt.
+    fn verify(&self, repair: bool, machine: Option<&str>) -> Result<StoreVerifyOutput> {
+        let system = match machine {
+            Some(name) => {
+                if !MachineConfig::list()?.iter().any(|cfg| cfg.name == name) {
+                    bail!("Machine {name} doesn't exist.");
+                }
+                let system = self
+                    .cmd()
+                    .run(
+                        "readlink",
+                        &[
+                            "-f",
+                            &store::DIR_CONFIG.join_machine(name).join_str("system").0,
+                        ],
+                    )
+                    .output_utf8_ok()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string();
+                if !system.starts_with("/nix/store/") {
+                    bail!("Machine {name} wasn't built yet.");
+                }
+                Some(system)
+            }
+            None => None,
+        };
+
+        set_progress_status("Calculating store paths...");
+        let paths = StorePaths::read(self)?;
+        let checked_paths = match &system {
+            Some(system) => paths.closure([system.as_str()]).len(),
+            None => paths.paths().count(),
+        };
+
+        set_progress_status(format!("Verifying {checked_paths} store paths..."));
+        // Signatures are not checked, because locally built paths (like the machines' systems)
+        // don't have any. nix fails if it finds problems, but its output is needed nevertheless,
+        // so its exit status is appended to the output.
+        let output = self
+            .cmd()
+            .script(format!(
+                "nix store verify --no-trust {repair} {target} 2>&1; echo \"{VERIFY_STATUS}$?\"",
+                repair = if repair { "--repair" } else { "" },
+                target = match &system {
+                    Some(system) => format!("--recursive {system}"),
+                    None => "--all".to_string(),
+                }
+            ))
+            .output_ok_streaming(channel().1, |line| {
+                log_progress("store_verify", log::Level::Warn, &line)
+            })?;
+
+        let status = output
+            .lines()
+            .find_map(|line| line.strip_prefix(VERIFY_STATUS)?.parse::<i32>().ok())
+            .context("nix store verify didn't finish")?;
+        let corrupted_paths = output
+            .lines()
+            .filter_map(|line| regex_captures!(r"path '(/nix/store/[^']+)' was modified", line))
+            .map(|(_, path)| path.to_string())
+            .collect::<Vec<_>>();
+        let mut errors = output
+            .lines()
+            .filter(|line| line.contains("error:"))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        // nix exits with 1 if paths are corrupted (even if they were repaired), and with other
+        // bits set if something else went wrong
+        let expected_status = if corrupted_paths.is_empty() { 0 } else { 1 };
+        if status != expected_status && errors.is_empty() {
+            errors.push(format!(
+                "nix store verify failed with exit status {status}."
+            ));
+        }
+
+        Ok(StoreVerifyOutput {
+            machine: machine.map(str::to_string),
+            repair,
+            checked_paths,
+            corrupted_paths,
+            errors,
+        })
+    }
+
+    /// Auto roots (e.g. from `nix build` or direnv) created inside a machine point to the machine's
+    /// file system, e.g. `/home/codchi/result`, which doesn't exist inside the store. Therefore
+    /// `nix store gc` would delete them as stale. Register the corresponding paths of the store's
+    /// view (`/data/machine/<name>/result`) as roots instead and remove the ones which are gone.
+    fn register_machine_gcroots(&self) -> Result<()> {
+        let script = format!(
+            r#"
+roots="{MACHINE_GCROOTS}"
+home="{home}"
+mkdir -p "$roots"
+for root in "$roots"/*/*; do
+    [ -e "$root" ] || rm -f "$root"
+done
+for link in /nix/var/nix/gcroots/auto/*; do
+    [ -L "$link" ] || continue
+    target="$(readlink "$link")"
+    case "$target" in
+        "$home"/*) ;;
+        *) continue ;;
+    esac
+    for machine in "$@"; do
+        path="{machine_dir}/$machine/${{target#"$home"/}}"
+        if [ -L "$path" ]; then
+            mkdir -p "$roots/$machine"
+            ln -sfn "$path" "$roots/$machine/$(basename "$link")"
+        fi
+    done
+done
+rmdir "$roots"/* 2>/dev/null || true
+"#,
+            home = consts::user::DEFAULT_HOME.0,
+            machine_dir = store::DIR_DATA.join_str(consts::MACHINE_PREFIX).0,
+        );
+        let names = MachineConfig::list()?
+            .into_iter()
+            .map(|cfg| cfg.name)
+            .collect::<Vec<_>>();
+        let mut args = vec!["-c", &script, "codchi-gcroots"];
+        args.extend(names.iter().map(String::as_str));
+        self.cmd().run("bash", &args).wait_ok()?;
         Ok(())
     }
 
+    /// Get the nix settings which are relevant for substitution, as seen by the store
+    fn nix_config(&self) -> Result<StoreNixConfig> {
+        let json: serde_json::Value = self
+            .cmd()
+            .run("nix", &["show-config", "--json"])
+            .output_json()?;
+        let strings = |key: &str| -> Vec<String> {
+            json[key]["value"]
+                .as_array()
+                .map(|values| {
+                    values
+                        .iter()
+                        .filter_map(|v| v.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        Ok(StoreNixConfig {
+            substituters: strings("substituters"),
+            trusted_public_keys: strings("trusted-public-keys"),
+            netrc_file: json["netrc-file"]["value"]
+                .as_str()
+                .filter(|path| !path.is_empty())
+                .map(str::to_string),
+            // never print the tokens themselves
+            access_tokens: json["access-tokens"]["value"]
+                .as_object()
+                .map(|tokens| tokens.keys().cloned().collect())
+                .unwrap_or_default(),
+        })
+    }
+
     fn _store_path_to_host(&self, path: &LinuxPath) -> anyhow::Result<PathBuf>;
 
     /// Resolve an absolute path with all symlinks resolved on the host. This only works reliable
@@ -128,3 +681,113 @@ using 'nix build' or direnv. Still procceed?",
     }
 }
 
+/// Write the user's nix settings from `CodchiConfig` to the store's data dir, where they are
+/// included by `/etc/nix/nix.conf`. They may contain credentials, so unlike the config dir, which
+/// is mounted into every code machine, the files are only visible to the store.
+fn write_nix_conf() -> Result<()> {
+    let cfg = &CodchiConfig::get().store;
+    // written by older versions
+    let old_dir = consts::host::DIR_CONFIG.join_store();
+    old_dir.join("netrc").remove();
+    old_dir.join("nix.conf").remove();
+
+    let host_dir = consts::host::DIR_DATA.join_store();
+    host_dir.get_or_create()?;
+    let netrc_path = match &cfg.netrc_file {
+        Some(netrc_file) => {
+            let netrc = fs::read(netrc_file)
+                .with_context(|| format!("Failed reading netrc file '{netrc_file}'"))?;
+            write_private_file(&host_dir.join("netrc"), &netrc)?;
+            Some(store::DIR_DATA.join_store().join_str("netrc").0)
+        }
+        None => {
+            host_dir.join("netrc").remove();
+            None
+        }
+    };
+    write_private_file(
+        &host_dir.join("nix.conf"),
+        cfg.to_nix_conf(netrc_path.as_deref()).as_bytes(),
+    )
+}
+
+/// Write a file which only its owner (root inside the store) can read
+fn write_private_file(path: &Path, content: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed writing {path:?}"))?;
+    // the mode above only applies to new files
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Concatenate the additional CA certificates from `CodchiConfig` into one bundle inside the
+/// store's config dir. It is added to the trust store of the store container and all machines on
+/// start.
+fn write_ca_certificates() -> Result<()> {
+    let target = consts::host::DIR_CONFIG.join_store().join("ca.crt");
+    let certs = &CodchiConfig::get().network.ca_certificates;
+    if certs.is_empty() {
+        target.remove();
+        return Ok(());
+    }
+    let mut file = File::create(target)?;
+    for cert in certs {
+        let content = fs::read_to_string(cert)
+            .with_context(|| format!("Failed reading CA certificate '{cert}'"))?;
+        writeln!(file, "{}", content.trim_end())?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60;
+    const NOW: u64 = 100 * DAY;
+    const GENERATIONS: [(u32, u64, bool); 5] = [
+        (1, NOW - 50 * DAY, false),
+        (2, NOW - 40 * DAY, true),
+        (4, NOW - 20 * DAY, false),
+        (5, NOW - 5 * DAY, false),
+        (6, NOW - DAY, false),
+    ];
+
+    #[test]
+    fn gc_policy_selects_generations() {
+        assert_eq!(wiped_generations(&GENERATIONS, None, 2, NOW), vec![1, 4]);
+        assert_eq!(wiped_generations(&GENERATIONS, Some(30), 2, NOW), vec![1]);
+        assert_eq!(
+            wiped_generations(&GENERATIONS, Some(0), 0, NOW),
+            vec![1, 4, 5, 6]
+        );
+        assert_eq!(
+            wiped_generations(&GENERATIONS, None, 10, NOW),
+            Vec::<u32>::new()
+        );
+    }
+
+    #[test]
+    fn newest_generation_before_cutoff_survives() {
+        // 4 was created before the cutoff, but was still active until 5 was created
+        assert_eq!(wiped_generations(&GENERATIONS, Some(10), 0, NOW), vec![1]);
+        // 1 is the only generation created before the cutoff
+        assert_eq!(
+            wiped_generations(&GENERATIONS, Some(45), 0, NOW),
+            Vec::<u32>::new()
+        );
+        assert_eq!(
+            wiped_generations(&GENERATIONS, Some(60), 0, NOW),
+            Vec::<u32>::new()
+        );
+    }
+}
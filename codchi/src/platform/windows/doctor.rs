@@ -0,0 +1,85 @@
+use super::{wsl, StoreImpl};
+use crate::{
+    config::DoctorCheck,
+    consts,
+    platform::{NixDriver, PlatformStatus, Store},
+};
+use version_compare::Version;
+
+const INSTALL_HINT: &str = "See <https://codchi.dev/introduction/installation#prerequisites>.";
+
+/// Windows specific checks of the host setup
+pub fn checks() -> Vec<DoctorCheck> {
+    vec![check_wsl(), check_store()]
+}
+
+/// Whether the store container is running and the nix daemon responds
+pub fn store_running() -> bool {
+    matches!(
+        wsl::get_platform_status(consts::CONTAINER_STORE_NAME),
+        Ok(PlatformStatus::Running)
+    ) && StoreImpl {}.cmd().ping_store()
+}
+
+fn check_wsl() -> DoctorCheck {
+    const NAME: &str = "WSL";
+    if let Err(err) = wsl::get_api() {
+        return DoctorCheck::fail(NAME, format!("{err:#}"), INSTALL_HINT);
+    }
+    match wsl::get_wsl_version() {
+        Ok(version_str) => {
+            let version = Version::from(&version_str);
+            if (Version::from(wsl::WSL_VERSION_MIN)..=Version::from(wsl::WSL_VERSION_MAX))
+                .contains(&version)
+            {
+                DoctorCheck::pass(NAME, format!("Version {version_str}"))
+            } else {
+                DoctorCheck::warn(
+                    NAME,
+                    format!(
+                        "Version {version_str} is untested. This version of codchi is only \
+tested with WSL versions between {} and {}.",
+                        wsl::WSL_VERSION_MIN,
+                        wsl::WSL_VERSION_MAX
+                    ),
+                    "Update codchi and WSL (`wsl --update`) to their latest versions.",
+                )
+            }
+        }
+        Err(err) => DoctorCheck::fail(NAME, format!("{err:#}"), INSTALL_HINT),
+    }
+}
+
+fn check_store() -> DoctorCheck {
+    const NAME: &str = "Store";
+    match wsl::get_platform_status(consts::CONTAINER_STORE_NAME) {
+        Ok(PlatformStatus::Running) => {
+            if (StoreImpl {}).cmd().ping_store() {
+                DoctorCheck::pass(NAME, "Running and the nix daemon responds")
+            } else {
+                DoctorCheck::fail(
+                    NAME,
+                    "The store container is running but the nix daemon doesn't respond.",
+                    format!(
+                        "Restart the store with `wsl --terminate {}`.",
+                        consts::CONTAINER_STORE_NAME
+                    ),
+                )
+            }
+        }
+        Ok(PlatformStatus::Stopped) => {
+            DoctorCheck::pass(NAME, "Stopped. It is started by the next codchi command.")
+        }
+        Ok(PlatformStatus::NotInstalled) => DoctorCheck::warn(
+            NAME,
+            "The store container is not installed yet.",
+            "It is installed by the next codchi command, e.g. `codchi status`.",
+        ),
+        Err(err) => DoctorCheck::fail(
+            NAME,
+            format!("Failed reading the status of the store container: {err:#}"),
+            "Try `codchi store recover` or see \
+<https://codchi.dev/introduction/troubleshooting>.",
+        ),
+    }
+}
@@ -1,10 +1,11 @@
 use self::wsl::wsl_command;
 use super::{
-    Driver, LinuxCommandTarget, LinuxUser, Machine, MachineDriver, NixDriver, PlatformStatus, Store,
+    shell_quote, Driver, LinuxCommandTarget, LinuxUser, Machine, MachineDriver, NixDriver,
+    PlatformStatus, Store,
 };
 use crate::util::{LinuxPath, PathExt, ResultExt, UtilExt};
 use crate::{
-    cli::DEBUG,
+    cli::{ExecOptions, DEBUG, OFFLINE},
     config::CodchiConfig,
     consts::{
         self, files,
@@ -29,6 +30,7 @@ use std::{
     time::Duration,
 };
 
+pub mod doctor;
 mod host;
 mod wsl;
 
@@ -232,6 +234,17 @@ impl MachineDriver for Machine {
                 if *DEBUG { "1" } else { "" }.to_string(),
             );
             env.insert("MACHINE_NAME".to_string(), self.config.name.clone());
+            let env_lines = env
+                .iter()
+                .map(|(key, value)| format!(r#"export CODCHI_{key}="{value}""#))
+                .chain(
+                    CodchiConfig::get()
+                        .network
+                        .proxy_env()
+                        .into_iter()
+                        .map(|(key, value)| format!(r#"export {key}="{value}""#)),
+                )
+                .collect_vec();
 
             // machine must run to write env file into it...
             let env_path = machine::CODCHI_ENV_TMP.to_host_path(&machine_name(&self.config.name));
@@ -259,8 +272,8 @@ impl MachineDriver for Machine {
                     .create(true)
                     .open(env_path)?;
 
-                for (key, value) in &env {
-                    writeln!(env_file, r#"export CODCHI_{key}="{value}""#)?;
+                for line in &env_lines {
+                    writeln!(env_file, "{line}")?;
                 }
                 env_file.sync_all()?;
 
@@ -295,9 +308,7 @@ cat << EOF > /mnt/wsl/codchi/.machine-init-env
 {}
 EOF
 "#,
-                    env.iter()
-                        .map(|(key, value)| format!(r#"export CODCHI_{key}="{value}""#))
-                        .join("\n")
+                    env_lines.join("\n")
                 ))
                 .wait_ok()?;
         }
@@ -333,6 +344,10 @@ tail -f "{log_file}"
             .trace_err("Failed cancelling output stream thread.")
             .ignore();
 
+        self.sync_git_identity()
+            .trace_err("Failed syncing git identity")
+            .ignore();
+
         Ok(())
     }
 
@@ -352,14 +367,34 @@ tail -f "{log_file}"
         Ok(())
     }
 
-    fn create_exec_cmd(&self, cmd: &[&str]) -> super::LinuxCommandBuilder {
-        let cmd = match cmd.split_first() {
-            Some((cmd, args)) => self.cmd().run(cmd, args),
+    fn create_exec_cmd(&self, cmd: &[&str], options: &ExecOptions) -> super::LinuxCommandBuilder {
+        // wsl.exe passes everything after `--` to the default shell of the user, which splits and
+        // expands it again. Therefore all arguments are quoted, so that `run` gets them as-is.
+        // wsl.exe only allocates a terminal if it runs in one, so `options.no_tty` needs no special
+        // handling.
+        let quoted = cmd.iter().map(|arg| shell_quote(arg)).collect_vec();
+        let cmd = match quoted.split_first() {
+            Some(_) if options.shell => self
+                .cmd()
+                .run("bash", &["-c", &shell_quote(&cmd.join(" "))]),
+            Some((cmd, args)) => self
+                .cmd()
+                .run(cmd, &args.iter().map(String::as_str).collect_vec()),
             None => self.cmd().run("bash", &["-l"]),
         };
 
-        cmd.with_cwd(consts::user::DEFAULT_HOME.clone())
-            .with_user(LinuxUser::Default)
+        cmd.with_cwd(
+            options
+                .guest_cwd()
+                .unwrap_or(consts::user::DEFAULT_HOME.clone()),
+        )
+        .with_env(options.env())
+        .with_user(options.user())
+    }
+
+    fn update_devices(&self) -> Result<()> {
+        // GPU, Wayland and audio are provided by WSL(g)
+        Ok(())
     }
 
     fn tar(&self, target_file: &std::path::Path) -> Result<()> {
@@ -416,7 +451,7 @@ impl LinuxCommandTarget for LinuxCommandDriver {
         &self,
         user: &Option<LinuxUser>,
         cwd: &Option<LinuxPath>,
-        _env: &HashMap<String, String>,
+        env: &HashMap<String, String>,
     ) -> std::process::Command {
         let mut cmd = wsl_command();
         cmd.args(["-d", &self.instance_name]);
@@ -424,6 +459,7 @@ impl LinuxCommandTarget for LinuxCommandDriver {
 
         // https://devblogs.microsoft.com/commandline/share-environment-vars-between-wsl-and-windows/
         cmd.env("CODCHI_DEBUG", if *DEBUG { "1" } else { "" });
+        cmd.env("CODCHI_OFFLINE", if *OFFLINE { "1" } else { "" });
         cmd.env("CODCHI_MACHINE_NAME", &self.instance_name); // only neccessary for machines, ignored in store
         cmd.env("CODCHI_IS_STORE", "1"); // only neccessary for store, ignored in machines
         cmd.env(
@@ -437,12 +473,27 @@ impl LinuxCommandTarget for LinuxCommandDriver {
             wslenv.push(":");
         }
         wslenv.push(
-            "CODCHI_DEBUG:CODCHI_MACHINE_NAME:CODCHI_IS_STORE:WSL_CODCHI_DIR_CONFIG/up:WSL_CODCHI_DIR_DATA/up",
+            "CODCHI_DEBUG:CODCHI_OFFLINE:CODCHI_MACHINE_NAME:CODCHI_IS_STORE:WSL_CODCHI_DIR_CONFIG/up:WSL_CODCHI_DIR_DATA/up",
         );
         if CodchiConfig::get().vcxsrv.enable {
             cmd.env("CODCHI_WSL_USE_VCXSRV", "1");
             wslenv.push(":CODCHI_WSL_USE_VCXSRV");
         }
+        for (key, value) in CodchiConfig::get().network.proxy_env() {
+            cmd.env(&key, value);
+            wslenv.push(":");
+            wslenv.push(key);
+        }
+        // thresholds of ndd's deadlock detection
+        for (key, _) in env::vars().filter(|(key, _)| key.starts_with("NDD_")) {
+            wslenv.push(":");
+            wslenv.push(key);
+        }
+        for (key, value) in env {
+            cmd.env(key, value);
+            wslenv.push(":");
+            wslenv.push(key);
+        }
         cmd.env("WSLENV", wslenv);
 
         match &user {
@@ -463,6 +514,6 @@ impl LinuxCommandTarget for LinuxCommandDriver {
     }
 
     fn quote_shell_arg(&self, arg: &str) -> String {
-        format!("'{}'", arg)
+        shell_quote(arg)
     }
 }
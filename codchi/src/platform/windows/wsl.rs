@@ -20,8 +20,8 @@ use sysinfo::System;
 use version_compare::Version;
 use wslapi::Library;
 
-const WSL_VERSION_MIN: &str = env!("CODCHI_WSL_VERSION_MIN");
-const WSL_VERSION_MAX: &str = env!("CODCHI_WSL_VERSION_MAX");
+pub const WSL_VERSION_MIN: &str = env!("CODCHI_WSL_VERSION_MIN");
+pub const WSL_VERSION_MAX: &str = env!("CODCHI_WSL_VERSION_MAX");
 
 pub fn wsl_command() -> Command {
     let mut cmd = Command::new("wsl.exe");
@@ -0,0 +1,135 @@
+use crate::config::MachineConfig;
+use crate::consts::{host, user, ToPath};
+use crate::logging::{hide_progress, set_progress_status};
+use crate::platform::*;
+use crate::util::PathExt;
+use anyhow::{bail, Context, Result};
+use std::{env, fs, path::PathBuf, process::Command};
+
+/// Private key of the ssh client. Its public key is authorized inside the machine.
+const CLIENT_KEY: &str = "id_ed25519";
+/// Private key of the sshd inside the machine
+const HOST_KEY: &str = "ssh_host_ed25519_key";
+/// Directory inside the machine where the keys are installed (see nix/nixos/driver/ssh.nix)
+const GUEST_SSH_DIR: &str = "/var/lib/codchi/ssh";
+
+fn host_alias(name: &str) -> String {
+    format!("codchi-{name}")
+}
+
+/// Per machine key material, outside of the directories which are visible to machines
+pub fn keys_dir(name: &str) -> PathBuf {
+    host::DIR_SSH.join(name)
+}
+
+/// Generate the client and host keys of a machine if they don't exist yet
+fn ensure_keys(name: &str) -> Result<PathBuf> {
+    // older versions kept the keys in the config dir, which every machine can read
+    host::DIR_CONFIG.join_machine(name).join("ssh").remove();
+
+    let dir = keys_dir(name);
+    for key in [CLIENT_KEY, HOST_KEY] {
+        let path = dir.join(key);
+        if path.exists() {
+            continue;
+        }
+        fs::create_dir_all(&dir)?;
+        let ssh_keygen = which::which("ssh-keygen")
+            .context("`ssh-keygen` not found. Please install an OpenSSH client.")?;
+        Command::new(ssh_keygen)
+            .args(["-q", "-t", "ed25519", "-N", ""])
+            .args(["-C", &format!("codchi@{name}")])
+            .arg("-f")
+            .arg(&path)
+            .wait_ok()
+            .with_context(|| format!("Failed generating ssh key {path:?}."))?;
+    }
+
+    let host_pub = fs::read_to_string(dir.join(format!("{HOST_KEY}.pub")))?;
+    let host_pub = host_pub
+        .split_whitespace()
+        .take(2)
+        .collect::<Vec<_>>()
+        .join(" ");
+    fs::write(
+        dir.join("known_hosts"),
+        format!("{} {host_pub}\n", host_alias(name)),
+    )?;
+    Ok(dir)
+}
+
+/// `Host` blocks for all machines, which can be included in `~/.ssh/config`
+pub fn config() -> Result<String> {
+    let codchi_exe = env::current_exe()?.display().to_string();
+    let mut config = String::new();
+    for cfg in MachineConfig::list()? {
+        let name = &cfg.name;
+        let dir = ensure_keys(name)?;
+        config.push_str(&format!(
+            r#"Host {alias}
+    HostName {alias}
+    User {user}
+    ProxyCommand "{codchi_exe}" ssh-proxy {name}
+    IdentityFile "{identity}"
+    IdentitiesOnly yes
+    UserKnownHostsFile "{known_hosts}"
+    HostKeyAlias {alias}
+
+"#,
+            alias = host_alias(name),
+            user = user::DEFAULT_NAME,
+            identity = dir.join(CLIENT_KEY).display(),
+            known_hosts = dir.join("known_hosts").display(),
+        ));
+    }
+    Ok(config)
+}
+
+/// Start the machine and run `sshd -i` inside it, which talks ssh over stdin / stdout. Never
+/// returns on success.
+pub fn proxy(name: &str) -> Result<()> {
+    let machine = Machine::by_name(name, true)?;
+    if machine.config_status == ConfigStatus::NotInstalled
+        || machine.platform_status == PlatformStatus::NotInstalled
+    {
+        bail!("Machine {name} wasn't installed yet. Install with `codchi rebuild {name}`.");
+    }
+    let dir = ensure_keys(name)?;
+
+    set_progress_status(format!("Starting {name}..."));
+    machine.start()?;
+    machine
+        .cmd()
+        .script(format!(
+            r#"
+set -e
+if [ ! -x /etc/codchi/sshd ]; then
+    echo "This machine doesn't support ssh yet. Please run 'codchi rebuild {name}'." >&2
+    exit 1
+fi
+mkdir -p -m 755 {GUEST_SSH_DIR}
+(umask 077; cat > {GUEST_SSH_DIR}/{HOST_KEY} <<'CODCHI_EOF'
+{host_key}
+CODCHI_EOF
+)
+cat > {GUEST_SSH_DIR}/authorized_keys <<'CODCHI_EOF'
+{client_pub}
+CODCHI_EOF
+chmod 644 {GUEST_SSH_DIR}/authorized_keys
+"#,
+            host_key = fs::read_to_string(dir.join(HOST_KEY))?.trim(),
+            client_pub = fs::read_to_string(dir.join(format!("{CLIENT_KEY}.pub")))?.trim(),
+        ))
+        .with_user(LinuxUser::Root)
+        .wait_ok()
+        .context("Failed installing ssh keys inside the machine.")?;
+    hide_progress();
+
+    // Without login shell, so nothing else is written to stdout
+    machine
+        .cmd()
+        .raw("/etc/codchi/sshd", &["-i", "-f", "/etc/codchi/sshd_config"])
+        .with_user(LinuxUser::Root)
+        .exec()?;
+    Ok(())
+}
@@ -201,6 +201,55 @@ pub fn store_path_base(path: &str) -> String {
         .to_string()
 }
 
+/// Format a size in bytes with binary prefixes, e.g. "1.5 GiB"
+pub fn human_size(bytes: u64) -> String {
+    use number_prefix::NumberPrefix;
+    match NumberPrefix::binary(bytes as f64) {
+        NumberPrefix::Standalone(bytes) => format!("{bytes} B"),
+        NumberPrefix::Prefixed(prefix, value) => format!("{value:.1} {prefix}B"),
+    }
+}
+
+/// Format a duration with the two largest units, e.g. "1h 5m", "3m 20s" or "4.2s"
+pub fn human_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 10 {
+        // truncated like the other units, so that e.g. 9.99s isn't shown as "10.0s"
+        let tenths = duration.as_millis() / 100;
+        format!("{}.{}s", tenths / 10, tenths % 10)
+    } else if secs < 60 {
+        format!("{secs}s")
+    } else if secs < 3600 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h {}m", secs / 3600, secs / 60 % 60)
+    }
+}
+
+/// Format seconds since the unix epoch as UTC time which is sortable and usable in file names,
+/// e.g. "2024-10-19_13-05-42"
+pub fn format_timestamp(secs: u64) -> String {
+    // civil_from_days from http://howardhinnant.github.io/date_algorithms.html
+    let days = secs / 86400;
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    let time = secs % 86400;
+    format!(
+        "{year:04}-{month:02}-{day:02}_{:02}-{:02}-{:02}",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
 pub trait PathExt: AsRef<Path> + Sized + Debug {
     /// Create the directory recursively if it doesn't exist and return its path
     fn get_or_create(&self) -> anyhow::Result<&Self> {
@@ -277,3 +326,40 @@ impl std::fmt::Display for LinuxPath {
         write!(f, "{}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_timestamps() {
+        assert_eq!(format_timestamp(0), "1970-01-01_00-00-00");
+        assert_eq!(format_timestamp(951782400), "2000-02-29_00-00-00");
+        assert_eq!(format_timestamp(1760870705), "2025-10-19_10-45-05");
+        // 2100 is no leap year
+        assert_eq!(format_timestamp(4107542399), "2100-02-28_23-59-59");
+        assert_eq!(format_timestamp(4107542400), "2100-03-01_00-00-00");
+        assert_eq!(format_timestamp(4107632523), "2100-03-02_01-02-03");
+    }
+
+    #[test]
+    fn format_durations() {
+        assert_eq!(human_duration(Duration::ZERO), "0.0s");
+        assert_eq!(human_duration(Duration::from_millis(4250)), "4.2s");
+        assert_eq!(human_duration(Duration::from_millis(9990)), "9.9s");
+        assert_eq!(human_duration(Duration::from_secs(10)), "10s");
+        assert_eq!(human_duration(Duration::from_millis(59999)), "59s");
+        assert_eq!(human_duration(Duration::from_secs(60)), "1m 0s");
+        assert_eq!(human_duration(Duration::from_secs(3599)), "59m 59s");
+        assert_eq!(human_duration(Duration::from_secs(3600)), "1h 0m");
+        assert_eq!(human_duration(Duration::from_secs(3900)), "1h 5m");
+    }
+
+    #[test]
+    fn format_sizes() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1024), "1.0 KiB");
+        assert_eq!(human_size(1536 * 1024 * 1024), "1.5 GiB");
+    }
+}
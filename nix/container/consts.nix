@@ -8,6 +8,8 @@
       DIR_CONFIG_MACHINE = "${consts.store.DIR_CONFIG}/machine/$CODCHI_MACHINE_NAME";
 
       DIR_DATA = "/data";
+      # only mounted into the store, e.g. for credentials
+      DIR_DATA_STORE = "${consts.store.DIR_DATA}/store";
       DIR_DATA_MACHINE = "${consts.store.DIR_DATA}/machine/$CODCHI_MACHINE_NAME";
 
       DIR_LOG = "${consts.store.DIR_DATA}/log";
@@ -74,8 +74,12 @@ in
         "/etc/passwd" = ./etc/passwd;
         # required for dns / other information lookup systems (mainly glibc)
         "/etc/nsswitch.conf" = ./etc/nsswitch.conf;
-        # nix settings
-        "/etc/nix/nix.conf" = ./etc/nix/nix.conf;
+        # nix settings. User settings (binary caches, credentials) are written by codchi to
+        # DIR_DATA_STORE/nix.conf, which isn't visible to code machines
+        "/etc/nix/nix.conf" = pkgs.writeText "nix.conf" ''
+          ${builtins.readFile ./etc/nix/nix.conf}
+          !include ${consts.store.DIR_DATA_STORE}/nix.conf
+        '';
         # force the nix registry to use the nixpkgs version from this repo
         "/etc/nix/registry.json" = pkgs.writeText "registry.json" (builtins.toJSON {
           version = 2;
@@ -150,6 +154,12 @@ in
         else
           ln -fs /etc/ssl/certs/nix.crt /etc/ssl/certs/ca-certificates.crt
         fi
+        # additional CA certificates from codchi's config, e.g. of a corporate proxy
+        if [ -s "${consts.store.DIR_CONFIG_STORE}/ca.crt" ]; then
+          cat "$(readlink -f /etc/ssl/certs/ca-certificates.crt)" "${consts.store.DIR_CONFIG_STORE}/ca.crt" \
+            > /etc/ssl/certs/ca-certificates.crt.tmp
+          mv -f /etc/ssl/certs/ca-certificates.crt.tmp /etc/ssl/certs/ca-certificates.crt
+        fi
       '';
     }
     {
@@ -178,7 +188,9 @@ in
             git add flake.*
           )
         fi
-        if [ -n "$(git -C "${consts.store.DIR_CONFIG_STORE}" diff)" ]; then
+        if [ -n "''${CODCHI_OFFLINE:-}" ]; then
+          logE "Offline mode: Not checking for store updates."
+        elif [ -n "$(git -C "${consts.store.DIR_CONFIG_STORE}" diff)" ]; then
           logE "Checking for updates..."
           ( cd "${consts.store.DIR_CONFIG_STORE}"
             nix $NIX_VERBOSITY flake update
@@ -194,10 +206,14 @@ in
           # remove impure git from default profile
           nix $NIX_VERBOSITY profile remove '.*'
           nix $NIX_VERBOSITY profile wipe-history
-        else
+        elif [ -z "''${CODCHI_OFFLINE:-}" ]; then
           logE "Updating store..."
-          nix flake update $NIX_VERBOSITY "${consts.store.DIR_CONFIG_STORE}"
-          ndd $NIX_VERBOSITY profile upgrade --profile "${consts.store.PROFILE_STORE}" '.*'
+          # don't fail without network, the installed store is still usable
+          if nix flake update $NIX_VERBOSITY "${consts.store.DIR_CONFIG_STORE}"; then
+            ndd $NIX_VERBOSITY profile upgrade --profile "${consts.store.PROFILE_STORE}" '.*'
+          else
+            logE "Failed to check for store updates. Continuing with the installed store..."
+          fi
         fi
 
         # kill $NIX_DAEMON_PID
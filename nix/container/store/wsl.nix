@@ -82,6 +82,17 @@ in
       }
 
       mkMnt "/data" "/mnt/wsl/codchi/data"
+
+      # nix settings with credentials are written by codchi.exe next to the store's disk, which
+      # isn't visible to code machines
+      mkdir -p "${consts.store.DIR_DATA_STORE}"
+      for file in nix.conf netrc; do
+        if [ -f "$WSL_CODCHI_DIR_DATA/store/$file" ]; then
+          install -m 600 "$WSL_CODCHI_DIR_DATA/store/$file" "${consts.store.DIR_DATA_STORE}/$file"
+        else
+          rm -f "${consts.store.DIR_DATA_STORE}/$file"
+        fi
+      done
       mkMnt "/nix"  "/mnt/wsl/codchi/nix"
     '';
 
@@ -9,6 +9,7 @@ in
     ./secrets.nix
     ./host-integration.nix
     ./init.nix
+    ./ssh.nix
   ];
 
   options.codchi.driver = {
@@ -33,6 +34,14 @@ in
       internal = true;
       default = { };
     };
+    hostConfigDir = mkOption {
+      type = types.nullOr types.str;
+      default = null;
+      internal = true;
+      description = ''
+        Path where codchi's config dir from the host is mounted inside the machine.
+      '';
+    };
   };
 
   config = mkMerge [
@@ -69,6 +78,25 @@ in
               '';
             };
 
+          # Add the CA certificates from codchi's config to the system trust store
+          "codchi-ca-certificates" = mkIf (config.codchi.driver.hostConfigDir != null) {
+            before = [ "network.target" ];
+            wantedBy = [ "multi-user.target" ];
+            serviceConfig.Type = "oneshot";
+            script = /* bash */ ''
+              extra="${config.codchi.driver.hostConfigDir}/store/ca.crt"
+              for bundle in ssl/certs/ca-certificates.crt ssl/certs/ca-bundle.crt pki/tls/certs/ca-bundle.crt; do
+                [ -e "/etc/static/$bundle" ] || continue
+                if [ -s "$extra" ]; then
+                  cat "/etc/static/$bundle" "$extra" > "/etc/$bundle.tmp"
+                  mv -f "/etc/$bundle.tmp" "/etc/$bundle"
+                else
+                  ln -sfn "/etc/static/$bundle" "/etc/$bundle"
+                fi
+              done
+            '';
+          };
+
           nix-daemon.enable = mkForce false;
           nix-gc.enable = mkForce false;
           nix-optimize.enable = mkForce false;
@@ -11,6 +11,7 @@ in
   config = mkIf cfg.enable {
 
     codchi.driver.name = "lxd";
+    codchi.driver.hostConfigDir = "/nix/var/nix/profiles/codchi";
     codchi.driver.iconCommand = lib.mkDefault ''
       cp "$ICON_PATH" "codchi/icons/"
     '';
@@ -24,6 +25,10 @@ in
         sed -i "s|a+ /var/log/journal|# OVERRIDDEN to setfacl -m on boot only\na+! /var/log/journal|" "$out"
       '');
 
+    # Sockets of the host (Wayland, PipeWire, PulseAudio, SSH and GPG agent)
+    # which are passed through by codchi. Don't clean them up.
+    systemd.tmpfiles.rules = [ "x /tmp/codchi-*" ];
+
     # Add the overrides from lxd distrobuilder
     # https://github.com/lxc/distrobuilder/blob/05978d0d5a72718154f1525c7d043e090ba7c3e0/distrobuilder/main.go#L630
     systemd.packages = [
@@ -62,6 +67,17 @@ in
           ${lib.getExe pkgs.xorg.xauth} add "$DISPLAY" . "$COOKIE"
         fi
       fi
+
+      # gpg can't be pointed to another agent socket, so link the gpg-agent of
+      # the host to where gpg expects its agent
+      if [ -S /tmp/codchi-gpg-agent ] && [ "$(id -u)" = 1000 ]; then
+        GPG_AGENT_SOCK="$(${pkgs.gnupg}/bin/gpgconf --list-dirs agent-socket)"
+        if [ "$(readlink "$GPG_AGENT_SOCK")" != /tmp/codchi-gpg-agent ]; then
+          mkdir -p -m 700 "$(dirname "$GPG_AGENT_SOCK")"
+          ln -sfn /tmp/codchi-gpg-agent "$GPG_AGENT_SOCK"
+        fi
+        unset GPG_AGENT_SOCK
+      fi
     '';
   };
 }
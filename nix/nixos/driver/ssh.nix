@@ -0,0 +1,34 @@
+{ lib, pkgs, config, ... }:
+let
+  inherit (lib) mkIf mkDefault;
+  sshd_config = pkgs.writeText "codchi-sshd-config" ''
+    # Used by `codchi ssh-proxy`, which runs `sshd -i` over stdin / stdout.
+    # No port is opened. Keys are managed by codchi.
+    HostKey /var/lib/codchi/ssh/ssh_host_ed25519_key
+    AuthorizedKeysFile /var/lib/codchi/ssh/authorized_keys
+    AllowUsers codchi
+    PermitRootLogin no
+    PasswordAuthentication no
+    KbdInteractiveAuthentication no
+    UsePAM yes
+    PrintMotd no
+    AcceptEnv LANG LC_*
+    Subsystem sftp ${config.programs.ssh.package}/libexec/sftp-server
+  '';
+in
+{
+  config = mkIf (config.codchi.driver.name != "none") {
+    environment.etc = {
+      "codchi/sshd".source = "${config.programs.ssh.package}/bin/sshd";
+      "codchi/sshd_config".source = sshd_config;
+    };
+
+    # Required by sshd, also if `services.openssh` is disabled
+    users.users.sshd = {
+      isSystemUser = mkDefault true;
+      group = mkDefault "sshd";
+    };
+    users.groups.sshd = { };
+    security.pam.services.sshd.startSession = mkDefault true;
+  };
+}
@@ -14,6 +14,7 @@ in
 
     codchi.driver = {
       name = "wsl";
+      hostConfigDir = "/nix/var/nix/profiles/global";
       iconCommand = lib.mkDefault ''
         ${pkgs.imagemagick}/bin/convert \
           -background transparent \
@@ -1,43 +1,218 @@
+//! Nix dont deadlock
+//! A wrapper for nix which monitors it for deadlocks (CPU & IO usage of nix's process tree). If a
+//! deadlock is detected, the nix child command is restarted with a backoff (this is ok because nix
+//! will continue with the build where it left)
+
+mod procfs;
+
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use procfs::{FileLock, Process, CLK_TCK};
+use std::collections::HashMap;
+use std::fs;
 use std::io::{self, BufRead, BufReader, Read, Write};
 use std::process::{exit, Child, Command, Stdio};
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::{env, thread};
-use sysinfo::{CpuRefreshKind, Networks, RefreshKind, System};
-
-/// Nix dont deadlock
-/// A wrapper for nix which monitors it for deadlocks (CPU & network usage). If a deadlock is
-/// detected, the nix child command is restarted (this is ok because nix will continue with the
-/// build where it left)
 
 const NIX_LOG_ERR: u64 = 0;
+const NIX_LOG_WARN: u64 = 1;
 // const NIX_LOG_DEBUG: u64 = 3;
 const NIX_LOG_TRACE: u64 = 4;
 
 // fake nix log message
 fn log(level: u64, msg: &str) {
-    eprintln!(r#"@nix {{ "action": "msg", "level": {level}, "msg": "ndd> {msg}" }}"#)
+    let msg = json_escape(&format!("ndd> {msg}"));
+    eprintln!(r#"@nix {{ "action": "msg", "level": {level}, "msg": "{msg}" }}"#)
+}
+
+fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Thresholds of the deadlock detection, configurable via environment variables
+struct Thresholds {
+    /// Nix is restarted if it was inactive for this long (`NDD_MAX_INACTIVE_SECS`)
+    max_inactive_duration: Duration,
+    /// CPU usage of nix's process tree (in % of one core) which counts as activity
+    /// (`NDD_MIN_CPU_PERCENT`)
+    min_cpu_percent: f64,
+    /// KB per second read by nix's process tree (e.g. downloads or the daemon socket) which
+    /// counts as activity (`NDD_MIN_NETWORK_KB`)
+    min_network_kb: u64,
+    /// Give up after restarting nix this many times (`NDD_MAX_RESTARTS`)
+    max_restarts: u32,
+}
+
+impl Thresholds {
+    fn from_env() -> Self {
+        Self::from_vars(&|name| env::var(name).ok())
+    }
+
+    /// Read the thresholds with `get_var`, falling back to the defaults for missing or invalid
+    /// values
+    fn from_vars(get_var: &dyn Fn(&str) -> Option<String>) -> Self {
+        fn var<T: FromStr>(get_var: &dyn Fn(&str) -> Option<String>, name: &str, default: T) -> T {
+            match get_var(name) {
+                Some(value) => value.parse().unwrap_or_else(|_| {
+                    log(
+                        NIX_LOG_WARN,
+                        &format!("Ignoring invalid value '{value}' of ${name}."),
+                    );
+                    default
+                }),
+                None => default,
+            }
+        }
+        Self {
+            max_inactive_duration: Duration::from_secs(var(get_var, "NDD_MAX_INACTIVE_SECS", 15)),
+            min_cpu_percent: var(get_var, "NDD_MIN_CPU_PERCENT", 2.0),
+            min_network_kb: var(get_var, "NDD_MIN_NETWORK_KB", 1),
+            max_restarts: var(get_var, "NDD_MAX_RESTARTS", 5),
+        }
+    }
+}
+
+/// Tell codchi that nix is restarted, so it can show the retry count
+fn report_restart(restart: u32, max_restarts: u32) {
+    eprintln!(r#"@nix {{ "action": "ndd_restart", "restart": {restart}, "max": {max_restarts} }}"#)
+}
+
+/// Kill nix and all its children. The nix daemon is left alone.
+fn kill_nix(child: &mut Child) {
+    // the child might have exited (and its tree is gone) since it was checked
+    for process in procfs::process_tree(&Process::all(), &[child.id()]) {
+        let _ = signal::kill(Pid::from_raw(process.pid as i32), Signal::SIGKILL);
+    }
+    child.kill().ok();
+    child
+        .wait()
+        .expect("Failed waiting for the killed nix process");
+}
+
+/// Delete the lock files in `/nix/store` which are not held by a live process anymore, e.g.
+/// because their nix process was killed. Locks of concurrent builds (e.g. of other machines) are
+/// kept.
+fn remove_stale_locks() {
+    let locks = FileLock::all();
+    for path in procfs::store_lock_files() {
+        if procfs::is_locked(&locks, &path) {
+            log(
+                NIX_LOG_TRACE,
+                &format!(
+                    "Keeping {}, which is held by another process.",
+                    path.display()
+                ),
+            );
+            continue;
+        }
+        // Like nix, mark the lock as stale before deleting it, so that a process which opened
+        // it in the meantime doesn't rely on it
+        let result = fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(b"d"))
+            .and_then(|_| fs::remove_file(&path));
+        match result {
+            Ok(()) => log(
+                NIX_LOG_TRACE,
+                &format!("Deleted stale lock {}", path.display()),
+            ),
+            Err(err) => log(
+                NIX_LOG_WARN,
+                &format!("Failed deleting stale lock {}: {err}", path.display()),
+            ),
+        }
+    }
+}
+
+/// The processes doing work for our nix command: Its process tree and, if nix talks to the
+/// daemon, the daemon's process tree.
+fn nix_processes(processes: &[Process], child: &Child) -> Vec<Process> {
+    let mut roots = vec![child.id()];
+    if env::var("NIX_REMOTE").is_ok_and(|remote| remote == "daemon") {
+        roots.extend(
+            processes
+                .iter()
+                .filter(|p| p.is_nix_daemon())
+                .map(|p| p.pid),
+        );
+    }
+    procfs::process_tree(processes, &roots)
+}
+
+/// Log the process tree of nix and the state of all nix locks
+fn log_snapshot(processes: &[Process], tree: &[Process]) {
+    log(
+        NIX_LOG_WARN,
+        "Processes of nix (pid, parent, state, command):",
+    );
+    for p in tree {
+        log(
+            NIX_LOG_WARN,
+            &format!("  {} {} {} {}", p.pid, p.ppid, p.state, p.cmdline.join(" ")),
+        );
+    }
+    let locks = FileLock::all();
+    log(NIX_LOG_WARN, "Locks of nix:");
+    for path in procfs::nix_lock_files() {
+        let describe = |lock: &FileLock| match lock.pid {
+            Some(pid) => {
+                let cmd = processes
+                    .iter()
+                    .find(|p| p.pid == pid)
+                    .map(|p| p.cmdline.join(" "))
+                    .unwrap_or_default();
+                format!("{pid} ({cmd})")
+            }
+            None => format!("an open file description ({})", lock.kind),
+        };
+        let on_file = locks.iter().filter(|lock| lock.is_on(&path));
+        let (waiting, holding): (Vec<&FileLock>, Vec<&FileLock>) =
+            on_file.partition(|lock| lock.waiting);
+        let mut msg = format!("  {}: ", path.display());
+        if holding.is_empty() {
+            msg.push_str("not held");
+        } else {
+            let holders: Vec<String> = holding.iter().map(|lock| describe(lock)).collect();
+            msg.push_str(&format!("held by {}", holders.join(", ")));
+        }
+        if !waiting.is_empty() {
+            let waiters: Vec<String> = waiting.iter().map(|lock| describe(lock)).collect();
+            msg.push_str(&format!(", waited for by {}", waiters.join(", ")));
+        }
+        log(NIX_LOG_WARN, &msg);
+    }
 }
 
 fn main() {
     let debug = std::env::var("CODCHI_DEBUG").is_ok();
+    let thresholds = Thresholds::from_env();
     let args: Vec<String> = env::args().skip(1).collect();
     let stdout_last_activity = Arc::new(Mutex::new(Instant::now()));
     let mut child = start_nix_process(&args, stdout_last_activity.clone());
 
     let check_interval = Duration::from_secs(1);
-    let max_inactive_duration = Duration::from_secs(15);
 
-    let mut sys = System::new_with_specifics(
-        RefreshKind::new().with_cpu(CpuRefreshKind::new().with_cpu_usage()),
-    );
-    let mut net = Networks::new_with_refreshed_list();
+    // CPU ticks and read bytes of each process of the last check
+    let mut last_usage: HashMap<u32, (u64, u64)> = HashMap::new();
+    let mut last_check = Instant::now();
     let mut last_activity = Instant::now();
+    let mut restarts = 0;
 
     loop {
-        sys.refresh_cpu();
-        net.refresh();
-
         if let Some(status) = child
             .try_wait()
             .expect("Failed while attempting to wait for nix...")
@@ -48,8 +223,25 @@ fn main() {
             );
             exit(status.code().unwrap_or(1))
         } else {
-            let cpu_usage = sys.global_cpu_info().cpu_usage();
-            let network_usage_kbs = net.iter().map(|(_, net)| net.received()).sum::<u64>() / 1_000; // 1 KB
+            let processes = Process::all();
+            let tree = nix_processes(&processes, &child);
+            let elapsed = last_check.elapsed().as_secs_f64().max(0.001);
+            last_check = Instant::now();
+
+            // Processes which are new since the last check are counted completely. The CPU time
+            // of finished children is added to their parent once they are waited for.
+            let (mut cpu_ticks, mut read_bytes) = (0, 0);
+            let mut usage = HashMap::with_capacity(tree.len());
+            for p in &tree {
+                let (last_ticks, last_read) = last_usage.get(&p.pid).copied().unwrap_or_default();
+                cpu_ticks += p.cpu_ticks.saturating_sub(last_ticks);
+                read_bytes += p.read_bytes.saturating_sub(last_read);
+                usage.insert(p.pid, (p.cpu_ticks, p.read_bytes));
+            }
+            last_usage = usage;
+
+            let cpu_usage = cpu_ticks as f64 / CLK_TCK as f64 / elapsed * 100.0;
+            let network_usage_kbs = (read_bytes as f64 / elapsed) as u64 / 1_000; // 1 KB
             let stdout_inactivity =
                 Instant::now().duration_since(*stdout_last_activity.lock().unwrap());
 
@@ -59,30 +251,49 @@ fn main() {
                 log(
                     NIX_LOG_TRACE,
                     &format!(
-                        "Nix considered inactive for {inactivity:?}. \
-CPU: {cpu_usage}, Network: {network_usage_kbs}, Stdout/err: {stdout_inactivity:?}"
+                        "Nix considered inactive for {inactivity:?}. Processes: {}, \
+CPU: {cpu_usage:.1}%, Read: {network_usage_kbs} KB/s, Stdout/err: {stdout_inactivity:?}",
+                        tree.len()
                     ),
                 );
             }
 
-            if cpu_usage > 2.0
-                || network_usage_kbs > 1
-                || stdout_inactivity <= max_inactive_duration
+            if cpu_usage > thresholds.min_cpu_percent
+                || network_usage_kbs > thresholds.min_network_kb
+                || stdout_inactivity <= thresholds.max_inactive_duration
             {
                 last_activity = Instant::now();
-            } else if inactivity > max_inactive_duration {
+            } else if inactivity > thresholds.max_inactive_duration {
                 log(
                     NIX_LOG_ERR,
-                    "Detected deadlock. Deleting locks and restarting nix...",
+                    &format!("Detected deadlock (no activity for {inactivity:?})."),
+                );
+                log_snapshot(&processes, &tree);
+                kill_nix(&mut child);
+                if restarts >= thresholds.max_restarts {
+                    log(
+                        NIX_LOG_ERR,
+                        &format!("Nix deadlocked after {restarts} restarts. Giving up."),
+                    );
+                    exit(1);
+                }
+                remove_stale_locks();
+
+                restarts += 1;
+                let backoff = Duration::from_secs(1 << (restarts - 1).min(5));
+                log(
+                    NIX_LOG_WARN,
+                    &format!(
+                        "Restarting nix in {backoff:?} ({restarts}/{})...",
+                        thresholds.max_restarts
+                    ),
                 );
-                child.kill().expect("Failed to kill nix process");
-                Command::new("bash")
-                    .args(["-c", "rm -f /nix/store/*.lock"])
-                    .spawn()
-                    .expect("Failed spawning the ndd lock remover.")
-                    .wait()
-                    .expect("Error while removing nix's locks");
+                report_restart(restarts, thresholds.max_restarts);
+                thread::sleep(backoff);
+
                 child = start_nix_process(&args, stdout_last_activity.clone());
+                *stdout_last_activity.lock().unwrap() = Instant::now();
+                last_usage.clear();
                 last_activity = Instant::now();
             }
         }
@@ -121,3 +332,39 @@ fn monitor_std_activity(
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_log_messages() {
+        assert_eq!(json_escape("plain"), "plain");
+        assert_eq!(
+            json_escape("\"quoted\" C:\\path\nnext\ttab"),
+            r#"\"quoted\" C:\\path\nnext\u0009tab"#
+        );
+    }
+
+    #[test]
+    fn thresholds_from_vars() {
+        let defaults = Thresholds::from_vars(&|_| None);
+        assert_eq!(defaults.max_inactive_duration, Duration::from_secs(15));
+        assert_eq!(defaults.min_cpu_percent, 2.0);
+        assert_eq!(defaults.min_network_kb, 1);
+        assert_eq!(defaults.max_restarts, 5);
+
+        let vars = HashMap::from([
+            ("NDD_MAX_INACTIVE_SECS", "60"),
+            ("NDD_MIN_CPU_PERCENT", "0.5"),
+            ("NDD_MIN_NETWORK_KB", "-3"),
+            ("NDD_MAX_RESTARTS", "ten"),
+        ]);
+        let thresholds = Thresholds::from_vars(&|name| vars.get(name).map(|v| v.to_string()));
+        assert_eq!(thresholds.max_inactive_duration, Duration::from_secs(60));
+        assert_eq!(thresholds.min_cpu_percent, 0.5);
+        // invalid values fall back to the defaults
+        assert_eq!(thresholds.min_network_kb, 1);
+        assert_eq!(thresholds.max_restarts, 5);
+    }
+}
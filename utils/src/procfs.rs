@@ -0,0 +1,295 @@
+//! Minimal parsers for `/proc` which are needed to monitor nix
+
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Clock ticks per second of the times in `/proc/<pid>/stat` (`USER_HZ`). This is 100 on all
+/// platforms codchi supports.
+pub const CLK_TCK: u64 = 100;
+
+#[derive(Debug, Clone)]
+pub struct Process {
+    pub pid: u32,
+    pub ppid: u32,
+    pub state: char,
+    /// CPU time of the process and its waited-for children in clock ticks
+    pub cpu_ticks: u64,
+    /// Bytes read by the process from files, pipes and sockets
+    pub read_bytes: u64,
+    pub cmdline: Vec<String>,
+}
+
+impl Process {
+    pub fn read(pid: u32) -> Option<Self> {
+        let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+        let io = fs::read_to_string(format!("/proc/{pid}/io")).unwrap_or_default();
+        let cmdline = fs::read(format!("/proc/{pid}/cmdline")).unwrap_or_default();
+        Self::parse(pid, &stat, &io, &cmdline)
+    }
+
+    /// Parse the contents of `/proc/<pid>/{stat,io,cmdline}`
+    fn parse(pid: u32, stat: &str, io: &str, cmdline: &[u8]) -> Option<Self> {
+        // The command name in parentheses might contain spaces. The remaining fields start with
+        // the state (field 3 in proc(5)).
+        let (_, rest) = stat.rsplit_once(')')?;
+        let fields: Vec<&str> = rest.split_whitespace().collect();
+        let state = fields.first()?.chars().next()?;
+        let ppid = fields.get(1)?.parse().ok()?;
+        // utime, stime, cutime, cstime
+        let cpu_ticks = fields
+            .get(11..15)?
+            .iter()
+            .map(|field| field.parse::<i64>().unwrap_or(0).max(0) as u64)
+            .sum();
+        let read_bytes = io
+            .lines()
+            .find_map(|line| line.strip_prefix("rchar:")?.trim().parse().ok())
+            .unwrap_or(0);
+        let cmdline = cmdline
+            .split(|b| *b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).to_string())
+            .collect();
+        Some(Self {
+            pid,
+            ppid,
+            state,
+            cpu_ticks,
+            read_bytes,
+            cmdline,
+        })
+    }
+
+    pub fn all() -> Vec<Self> {
+        fs::read_dir("/proc")
+            .map(|dir| {
+                dir.filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+                    .filter_map(Self::read)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Whether this is the main process or a worker of the nix daemon
+    pub fn is_nix_daemon(&self) -> bool {
+        let program = self
+            .cmdline
+            .first()
+            .and_then(|arg| Path::new(arg).file_name())
+            .and_then(|name| name.to_str());
+        program == Some("nix-daemon")
+            || (program == Some("nix") && self.cmdline.get(1).is_some_and(|arg| arg == "daemon"))
+    }
+}
+
+/// The processes in `roots` and all their descendants
+pub fn process_tree(processes: &[Process], roots: &[u32]) -> Vec<Process> {
+    let mut tree: Vec<Process> = processes
+        .iter()
+        .filter(|p| roots.contains(&p.pid))
+        .cloned()
+        .collect();
+    let mut i = 0;
+    while i < tree.len() {
+        let pid = tree[i].pid;
+        tree.extend(
+            processes
+                .iter()
+                .filter(|p| p.ppid == pid && !roots.contains(&p.pid))
+                .cloned(),
+        );
+        i += 1;
+    }
+    tree
+}
+
+/// A POSIX / flock / OFD lock from `/proc/locks`
+#[derive(Debug, Clone)]
+pub struct FileLock {
+    /// `None` for OFD locks, which don't belong to a process
+    pub pid: Option<u32>,
+    /// The process waits for the lock instead of holding it
+    pub waiting: bool,
+    pub kind: String,
+    pub dev: (u64, u64),
+    pub inode: u64,
+}
+
+impl FileLock {
+    pub fn all() -> Vec<Self> {
+        fs::read_to_string("/proc/locks")
+            .map(|locks| locks.lines().filter_map(Self::parse).collect())
+            .unwrap_or_default()
+    }
+
+    /// Parse a line like `1: POSIX  ADVISORY  WRITE 1234 00:2d:5678 0 EOF`. Waiting processes
+    /// are marked with `->` after the id.
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace().skip(1).peekable();
+        let waiting = fields.next_if_eq(&"->").is_some();
+        let kind = fields.next()?.to_string();
+        let pid = fields.nth(2)?.parse::<i64>().ok()?;
+        let mut dev_inode = fields.next()?.split(':');
+        let major = u64::from_str_radix(dev_inode.next()?, 16).ok()?;
+        let minor = u64::from_str_radix(dev_inode.next()?, 16).ok()?;
+        let inode = dev_inode.next()?.parse().ok()?;
+        Some(Self {
+            pid: u32::try_from(pid).ok().filter(|pid| *pid > 0),
+            waiting,
+            kind,
+            dev: (major, minor),
+            inode,
+        })
+    }
+
+    /// Whether this lock is on the file at `path`
+    pub fn is_on(&self, path: &Path) -> bool {
+        fs::metadata(path)
+            .is_ok_and(|meta| meta.ino() == self.inode && dev_major_minor(meta.dev()) == self.dev)
+    }
+}
+
+/// Split a `dev_t` into its major and minor number, like `gnu_dev_major` / `gnu_dev_minor`
+fn dev_major_minor(dev: u64) -> (u64, u64) {
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & 0xffff_f000);
+    let minor = (dev & 0xff) | ((dev >> 12) & 0xffff_ff00);
+    (major, minor)
+}
+
+/// Whether a live process (or an open file description) holds a lock on `path`
+pub fn is_locked(locks: &[FileLock], path: &Path) -> bool {
+    locks.iter().any(|lock| {
+        !lock.waiting
+            && lock.is_on(path)
+            && lock
+                .pid
+                .is_none_or(|pid| Path::new(&format!("/proc/{pid}")).exists())
+    })
+}
+
+/// Lock files of store paths which are built or substituted (`/nix/store/*.lock`)
+pub fn store_lock_files() -> Vec<PathBuf> {
+    fs::read_dir("/nix/store")
+        .map(|dir| {
+            dir.filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "lock"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Lock files of the nix store, its database and the garbage collector
+pub fn nix_lock_files() -> Vec<PathBuf> {
+    let mut files = store_lock_files();
+    files.extend(
+        ["/nix/var/nix/db/big-lock", "/nix/var/nix/gc.lock"]
+            .into_iter()
+            .map(PathBuf::from)
+            .filter(|path| path.exists()),
+    );
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: u32, ppid: u32) -> Process {
+        Process {
+            pid,
+            ppid,
+            state: 'S',
+            cpu_ticks: 0,
+            read_bytes: 0,
+            cmdline: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn parse_stat_with_parens_in_comm() {
+        let stat = "4242 (nix) (build) S 4200 4242 4200 0 -1 4194560 1000 0 0 0 \
+120 30 5 -1 20 0 3 0 12345 0 0";
+        let io = "rchar: 98765\nwchar: 100\nsyscr: 10\n";
+        let process = Process::parse(4242, stat, io, b"nix\0build\0.#default\0").unwrap();
+        assert_eq!(process.state, 'S');
+        assert_eq!(process.ppid, 4200);
+        // a negative cutime is ignored
+        assert_eq!(process.cpu_ticks, 155);
+        assert_eq!(process.read_bytes, 98765);
+        assert_eq!(process.cmdline, ["nix", "build", ".#default"]);
+
+        assert!(Process::parse(1, "1 (init", "", b"").is_none());
+    }
+
+    #[test]
+    fn parse_posix_lock_holder() {
+        let lock = FileLock::parse("1: POSIX  ADVISORY  WRITE 1234 00:2d:5678 0 EOF").unwrap();
+        assert_eq!(lock.pid, Some(1234));
+        assert!(!lock.waiting);
+        assert_eq!(lock.kind, "POSIX");
+        assert_eq!(lock.dev, (0, 0x2d));
+        assert_eq!(lock.inode, 5678);
+    }
+
+    #[test]
+    fn parse_waiting_lock() {
+        let lock = FileLock::parse("1: -> FLOCK  ADVISORY  WRITE 2345 fd:01:131 0 EOF").unwrap();
+        assert_eq!(lock.pid, Some(2345));
+        assert!(lock.waiting);
+        assert_eq!(lock.kind, "FLOCK");
+        assert_eq!(lock.dev, (0xfd, 1));
+        assert_eq!(lock.inode, 131);
+    }
+
+    #[test]
+    fn parse_ofd_lock() {
+        let lock = FileLock::parse("3: OFDLCK ADVISORY  READ  -1 08:02:1311 0 EOF").unwrap();
+        assert_eq!(lock.pid, None);
+        assert!(!lock.waiting);
+        assert_eq!(lock.kind, "OFDLCK");
+        assert_eq!(lock.dev, (8, 2));
+        assert_eq!(lock.inode, 1311);
+
+        assert!(FileLock::parse("").is_none());
+    }
+
+    #[test]
+    fn split_dev() {
+        assert_eq!(dev_major_minor(0x0801), (8, 1));
+        assert_eq!(dev_major_minor(0x10303), (259, 3));
+        // numbers which need the extended bits, encoded like glibc's makedev
+        let (major, minor) = (0x1234_u64, 0x12345_u64);
+        let dev = ((major & 0xfff) << 8)
+            | ((major & !0xfff) << 32)
+            | (minor & 0xff)
+            | ((minor & !0xff) << 12);
+        assert_eq!(dev_major_minor(dev), (major, minor));
+    }
+
+    #[test]
+    fn nested_process_tree() {
+        let processes = [
+            process(1, 0),
+            process(10, 1),
+            process(11, 10),
+            process(12, 11),
+            process(13, 10),
+            process(20, 1),
+            process(21, 20),
+        ];
+        let pids = |roots: &[u32]| {
+            let mut pids: Vec<u32> = process_tree(&processes, roots)
+                .iter()
+                .map(|p| p.pid)
+                .collect();
+            pids.sort();
+            pids
+        };
+        assert_eq!(pids(&[10]), [10, 11, 12, 13]);
+        assert_eq!(pids(&[11, 21]), [11, 12, 21]);
+        // a root which is a descendant of another one is only included once
+        assert_eq!(pids(&[10, 11]), [10, 11, 12, 13]);
+        assert_eq!(pids(&[99]), Vec::<u32>::new());
+    }
+}